@@ -1,24 +1,18 @@
 extern crate squirrel;
 
-use squirrel::{SquirrelVM,};
-use std::io::{Write, stdin, stdout, stderr};
+use squirrel::EngineBuilder;
+use std::io::{Write, stdin, stdout};
 
 fn main() {
-	let mut squirrel = SquirrelVM::new(1024, stdout(), stderr());
+	let mut squirrel = EngineBuilder::stdio()
+		.on_runtime_error(|e| {
+			println!("{} (line {})", e.value, e.line);
+		})
+		.build()
+		.unwrap();
 	
 	squirrel.push_root_table();
 	
-	squirrel.register_blob_lib().unwrap();
-	squirrel.register_io_lib().unwrap();
-	squirrel.register_system_lib().unwrap();
-	squirrel.register_math_lib().unwrap();
-	squirrel.register_string_lib().unwrap();
-	
-	squirrel.compile_str("seterrorhandler(function(x) { print(x + \"\\n\") })", "error_handler").unwrap();
-	squirrel.push_root_table();
-	squirrel.call(1, false, true).unwrap();
-	squirrel.pop(1);
-	
 	let mut stdin = stdin();
 	
 	loop {
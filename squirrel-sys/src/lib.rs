@@ -101,6 +101,7 @@ pub const _RT_WEAKREF: isize = 0x00010000;
 pub const _RT_OUTER: isize = 0x00020000;
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SQObjectType {
 	OT_NULL =			(_RT_NULL|SQOBJECT_CANBEFALSE),
 	OT_INTEGER =		(_RT_INTEGER|SQOBJECT_NUMERIC|SQOBJECT_CANBEFALSE),
@@ -123,11 +124,13 @@ pub enum SQObjectType {
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SQObjectValue {
 	pub raw: SQRawObjectVal
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SQObject {
 	pub _type: SQObjectType,
 	pub _unVal: SQObjectValue
@@ -6,8 +6,8 @@ pub type SQRexBool = c_uint;
 
 #[repr(C)]
 pub struct SQRexMatch {
-	begin: *const SQChar,
-	len: SQInteger
+	pub begin: *const SQChar,
+	pub len: SQInteger
 }
 
 extern {
@@ -0,0 +1,295 @@
+//! A handle based interface to Squirrel arrays.
+
+use ffi;
+use std::cmp::Ordering;
+use std::io::Write;
+
+use {SquirrelVM, SquirrelError, FromSquirrel, ToSquirrel, Handle, Value};
+
+/// An array held by a handle, which can be manipulated without managing the stack.
+///
+/// Every method leaves the stack as it found it. Elements are read and written directly, so no metamethods are called.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// vm.compile_str("return [3, 1, 2]", "sort").unwrap();
+/// vm.push_root_table();
+/// vm.call(1, true, true).unwrap();
+/// let mut array = vm.array(-1).unwrap();
+/// array.sort().unwrap();
+/// assert_eq!(array.get::<i32>(0).unwrap(), 1);
+/// ```
+pub struct ArrayRef<'a, P: Write + Sync + 'a, E: Write + Sync + 'a> {
+	vm: &'a mut SquirrelVM<P, E>,
+	handle: Handle
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> ArrayRef<'a, P, E> {
+	/// Wraps a handle, which must refer to an array.
+	pub fn new(vm: &'a mut SquirrelVM<P, E>, handle: Handle) -> Result<ArrayRef<'a, P, E>, SquirrelError> {
+		if handle.object_type() != ffi::SQObjectType::OT_ARRAY {
+			return Err(SquirrelError::Runtime("the handle does not refer to an array".to_string()));
+		}
+		Ok(ArrayRef {
+			vm: vm,
+			handle: handle
+		})
+	}
+
+	/// Gets the handle of the array.
+	pub fn handle(&self) -> &Handle {
+		&self.handle
+	}
+
+	/// Gets the number of elements.
+	pub fn len(&mut self) -> usize {
+		let size = self.with(|vm| unsafe { ffi::sq_getsize(vm.0, -1) });
+		if size < 0 { 0 } else { size as usize }
+	}
+
+	pub fn is_empty(&mut self) -> bool {
+		self.len() == 0
+	}
+
+	/// Reads the element at `i`.
+	pub fn get<T: FromSquirrel>(&mut self, i: usize) -> Result<T, SquirrelError> {
+		try!(self.check_index(i, false));
+		self.with(|vm| {
+			vm.push_integer(i as ffi::SQInteger);
+			unsafe { ffi::sq_rawget(vm.0, -2); }
+			vm.read(-1)
+		})
+	}
+
+	/// Replaces the element at `i`.
+	pub fn set<T: ToSquirrel>(&mut self, i: usize, value: T) -> Result<(), SquirrelError> {
+		try!(self.check_index(i, false));
+		self.try_with(|vm| {
+			vm.push_integer(i as ffi::SQInteger);
+			vm.push_value(value);
+			vm.raw_set(-3)
+		})
+	}
+
+	/// Appends an element.
+	pub fn push<T: ToSquirrel>(&mut self, value: T) -> Result<(), SquirrelError> {
+		self.try_with(|vm| {
+			vm.push_value(value);
+			vm.array_append(-2)
+		})
+	}
+
+	/// Removes the last element and reads it, failing if the array is empty.
+	pub fn pop<T: FromSquirrel>(&mut self) -> Result<T, SquirrelError> {
+		let len = self.len();
+		if len == 0 {
+			return Err(SquirrelError::Runtime("cannot pop from an empty array".to_string()));
+		}
+		let value = try!(self.get(len - 1));
+		try!(self.try_with(|vm| vm.array_pop(-1, false)));
+		Ok(value)
+	}
+
+	/// Inserts an element at `i`, moving the elements after it along. `i` may be the length of the array.
+	pub fn insert<T: ToSquirrel>(&mut self, i: usize, value: T) -> Result<(), SquirrelError> {
+		try!(self.check_index(i, true));
+		if i == self.len() {
+			return self.push(value);
+		}
+		self.try_with(|vm| {
+			vm.push_value(value);
+			vm.array_insert(-2, i as isize)
+		})
+	}
+
+	/// Removes the element at `i` and reads it, moving the elements after it back.
+	pub fn remove<T: FromSquirrel>(&mut self, i: usize) -> Result<T, SquirrelError> {
+		let value = try!(self.get(i));
+		try!(self.try_with(|vm| vm.array_remove(-1, i as isize)));
+		Ok(value)
+	}
+
+	/// Reverses the order of the elements.
+	pub fn reverse(&mut self) -> Result<(), SquirrelError> {
+		self.try_with(|vm| vm.array_reverse(-1))
+	}
+
+	/// Changes the number of elements, filling new ones with `null`.
+	pub fn resize(&mut self, len: usize) -> Result<(), SquirrelError> {
+		self.try_with(|vm| vm.array_resize(-1, len))
+	}
+
+	/// Creates a new array holding the elements from `start` up to `end`.
+	pub fn slice(&mut self, start: usize, end: usize) -> Result<Handle, SquirrelError> {
+		let len = self.len();
+		if start > end || end > len {
+			return Err(SquirrelError::Runtime(format!("slice {}..{} is out of range for an array of length {}", start, end, len)));
+		}
+		Ok(self.with(|vm| {
+			vm.new_array(end - start);
+			for i in start..end {
+				vm.push_integer((i - start) as ffi::SQInteger);
+				vm.push_integer(i as ffi::SQInteger);
+				unsafe { ffi::sq_rawget(vm.0, -4); }
+				let _ = vm.raw_set(-3);
+			}
+			vm.handle(-1)
+		}))
+	}
+
+	/// Reads every element.
+	pub fn to_vec<T: FromSquirrel>(&mut self) -> Result<Vec<T>, SquirrelError> {
+		self.with(|vm| vm.read(-1))
+	}
+
+	/// Sorts the elements like the `<=>` operator, calling the `_cmp` metamethod of instances and tables.
+	///
+	/// Fails without changing the array if two elements cannot be compared, such as a string and an integer.
+	/// The sort is stable.
+	pub fn sort(&mut self) -> Result<(), SquirrelError> {
+		let values: Vec<Value> = try!(self.to_vec());
+		let values = try!(merge_sort(values, &mut |a, b| a.partial_cmp(b).ok_or_else(|| {
+			SquirrelError::Runtime(format!("cannot compare {} with {}", a.type_name(), b.type_name()))
+		})));
+		self.write_all(&values)
+	}
+
+	/// Sorts the elements with a comparator. The sort is stable.
+	///
+	/// The comparator need not be a total order, so it may call into scripts.
+	pub fn sort_by<F: FnMut(&Value, &Value) -> Ordering>(&mut self, mut compare: F) -> Result<(), SquirrelError> {
+		let values: Vec<Value> = try!(self.to_vec());
+		let values = try!(merge_sort(values, &mut |a, b| Ok(compare(a, b))));
+		self.write_all(&values)
+	}
+
+	/// Overwrites the elements in order, which must not be more than the array holds.
+	fn write_all(&mut self, values: &[Value]) -> Result<(), SquirrelError> {
+		self.try_with(|vm| {
+			for (i, value) in values.iter().enumerate() {
+				vm.push_integer(i as ffi::SQInteger);
+				vm.push_value(value);
+				try!(vm.raw_set(-3));
+			}
+			Ok(())
+		})
+	}
+
+	fn check_index(&mut self, i: usize, allow_end: bool) -> Result<(), SquirrelError> {
+		let len = self.len();
+		if i < len || (allow_end && i == len) {
+			Ok(())
+		}
+		else {
+			Err(SquirrelError::Runtime(format!("index {} is out of range for an array of length {}", i, len)))
+		}
+	}
+
+	/// Calls `f` with the array pushed, restoring the stack afterwards.
+	fn with<T, F: FnOnce(&mut SquirrelVM<P, E>) -> T>(&mut self, f: F) -> T {
+		let mut vm = self.vm.guard();
+		vm.push_handle(&self.handle);
+		f(&mut vm)
+	}
+
+	/// Like `with`, but turns a failed operation into the error it raised.
+	fn try_with<F: FnOnce(&mut SquirrelVM<P, E>) -> Result<(), ()>>(&mut self, f: F) -> Result<(), SquirrelError> {
+		self.with(|vm| match f(vm) {
+			Ok(()) => Ok(()),
+			Err(()) => Err(SquirrelError::Runtime(vm.take_last_error()))
+		})
+	}
+}
+
+/// Sorts values with `compare`, failing at the first pair it fails on.
+///
+/// `slice::sort_by` may panic if the comparison is not a total order, which `_cmp` metamethods and
+/// comparators written in script cannot promise, so this merge sort is used instead.
+fn merge_sort<F>(mut values: Vec<Value>, compare: &mut F) -> Result<Vec<Value>, SquirrelError>
+	where F: FnMut(&Value, &Value) -> Result<Ordering, SquirrelError>
+{
+	if values.len() <= 1 {
+		return Ok(values);
+	}
+	let right = try!(merge_sort(values.split_off(values.len() / 2), compare));
+	let left = try!(merge_sort(values, compare));
+
+	let mut merged = Vec::with_capacity(left.len() + right.len());
+	let mut left = left.into_iter().peekable();
+	let mut right = right.into_iter().peekable();
+	loop {
+		// Take from the left unless the right is smaller, which keeps the sort stable
+		let take_right = match (left.peek(), right.peek()) {
+			(Some(a), Some(b)) => try!(compare(a, b)) == Ordering::Greater,
+			(Some(_), None) => false,
+			(None, Some(_)) => true,
+			(None, None) => break
+		};
+		merged.extend(if take_right { right.next() } else { left.next() });
+	}
+	Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cmp::Ordering;
+	use super::merge_sort;
+	use {SquirrelError, Value};
+
+	fn by_value(a: &Value, b: &Value) -> Result<Ordering, SquirrelError> {
+		a.partial_cmp(b).ok_or_else(|| SquirrelError::Runtime(format!("cannot compare {} with {}", a.type_name(), b.type_name())))
+	}
+
+	fn name(value: &Value) -> &str {
+		match *value {
+			Value::String(ref s) => s,
+			_ => panic!("not a string")
+		}
+	}
+
+	#[test]
+	fn sorts_numbers() {
+		let values = vec![Value::Integer(3), Value::Float(1.5), Value::Integer(-2), Value::Float(3.0), Value::Integer(0)];
+		let sorted = merge_sort(values, &mut by_value).unwrap();
+		assert_eq!(sorted, vec![Value::Integer(-2), Value::Integer(0), Value::Float(1.5), Value::Integer(3), Value::Float(3.0)]);
+	}
+
+	#[test]
+	fn sorts_strings() {
+		let values = vec!["pear", "apple", "fig", "Banana"].into_iter().map(|s| Value::String(s.to_string())).collect();
+		let sorted = merge_sort(values, &mut by_value).unwrap();
+		let names: Vec<_> = sorted.iter().map(name).collect();
+		assert_eq!(names, ["Banana", "apple", "fig", "pear"]);
+	}
+
+	#[test]
+	fn stable() {
+		let values = vec!["bb", "a", "cc", "d", "ee"].into_iter().map(|s| Value::String(s.to_string())).collect();
+		let sorted = merge_sort(values, &mut |a, b| Ok(name(a).len().cmp(&name(b).len()))).unwrap();
+		let names: Vec<_> = sorted.iter().map(name).collect();
+		assert_eq!(names, ["a", "d", "bb", "cc", "ee"]);
+	}
+
+	#[test]
+	fn incomparable() {
+		let values = vec![Value::Integer(1), Value::String("one".to_string())];
+		match merge_sort(values, &mut by_value) {
+			Err(SquirrelError::Runtime(message)) => assert_eq!(message, "cannot compare integer with string"),
+			other => panic!("{:?}", other)
+		}
+	}
+
+	#[test]
+	fn not_total() {
+		// A comparator that is not an order must not panic
+		let values: Vec<_> = (0..20).map(Value::Integer).collect();
+		let mut calls = 0;
+		let sorted = merge_sort(values, &mut |_, _| {
+			calls += 1;
+			Ok(if calls % 3 == 0 { Ordering::Greater } else { Ordering::Less })
+		}).unwrap();
+		assert_eq!(sorted.len(), 20);
+	}
+}
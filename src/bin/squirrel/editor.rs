@@ -0,0 +1,450 @@
+//! A small line editor with history and tab completion.
+//!
+//! The terminal is switched to raw mode while a line is read, so that keys can be handled as they are typed.
+//! When standard input is not a terminal, or on platforms other than Unix, lines are read without editing.
+
+#[cfg(unix)]
+use libc;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// The most lines of history that are saved.
+const MAX_HISTORY: usize = 1000;
+
+/// The result of reading a line.
+pub enum Input {
+	/// A line was entered.
+	Line(String),
+	/// Ctrl-C was pressed, discarding the line.
+	Interrupted,
+	/// The end of input was reached, or Ctrl-D was pressed on an empty line.
+	Eof
+}
+
+/// Reads lines, remembering earlier ones.
+pub struct Editor {
+	history: Vec<String>,
+	history_path: Option<PathBuf>
+}
+
+impl Editor {
+	/// Creates an editor, loading history from `~/.squirrel_history`.
+	pub fn new() -> Editor {
+		let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(".squirrel_history"));
+		let history = history_path.as_ref()
+			.and_then(|path| File::open(path).ok())
+			.map(|f| BufReader::new(f).lines().map_while(Result::ok).collect())
+			.unwrap_or_default();
+		Editor {
+			history: history,
+			history_path: history_path
+		}
+	}
+
+	/// Adds a line to the history, unless it repeats the last one.
+	pub fn add_history(&mut self, line: &str) {
+		if !line.trim().is_empty() && self.history.last().is_none_or(|last| last != line) {
+			self.history.push(line.to_string());
+		}
+	}
+
+	/// Saves the most recent history to `~/.squirrel_history`.
+	pub fn save_history(&self) {
+		if let Some(ref path) = self.history_path {
+			let start = self.history.len().saturating_sub(MAX_HISTORY);
+			let _ = File::create(path).and_then(|mut f| {
+				for line in &self.history[start..] {
+					try!(writeln!(f, "{}", line));
+				}
+				Ok(())
+			});
+		}
+	}
+
+	/// Reads a line, completing words from `words` when Tab is pressed.
+	pub fn read_line(&mut self, prompt: &str, words: &[String]) -> io::Result<Input> {
+		print!("{}", prompt);
+		try!(io::stdout().flush());
+
+		#[cfg(unix)]
+		{
+			if let Some(raw) = RawMode::enable() {
+				let result = LineState::new(prompt, &self.history, words).run();
+				drop(raw);
+				println!();
+				return result;
+			}
+		}
+		#[cfg(not(unix))]
+		let _ = words;
+
+		let mut line = String::new();
+		if try!(io::stdin().read_line(&mut line)) == 0 {
+			return Ok(Input::Eof);
+		}
+		let len = line.trim_end_matches(['\n', '\r']).len();
+		line.truncate(len);
+		Ok(Input::Line(line))
+	}
+}
+
+#[cfg(unix)]
+/// Puts the terminal into raw mode, restoring it when dropped.
+struct RawMode {
+	original: libc::termios
+}
+
+#[cfg(unix)]
+impl RawMode {
+	/// Enables raw mode, or returns `None` if standard input is not a terminal.
+	fn enable() -> Option<RawMode> {
+		unsafe {
+			if libc::isatty(libc::STDIN_FILENO) == 0 {
+				return None;
+			}
+			let mut original = ::std::mem::zeroed();
+			if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+				return None;
+			}
+			let mut raw = original;
+			// Ctrl-C is handled as a key, so that it only discards the current line
+			raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+			raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+			raw.c_cc[libc::VMIN] = 1;
+			raw.c_cc[libc::VTIME] = 0;
+			if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) != 0 {
+				return None;
+			}
+			Some(RawMode {
+				original: original
+			})
+		}
+	}
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+	fn drop(&mut self) {
+		unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.original); }
+	}
+}
+
+#[cfg(unix)]
+/// The line being edited.
+struct LineState<'a> {
+	prompt: &'a str,
+	history: &'a [String],
+	words: &'a [String],
+	chars: Vec<char>,
+	cursor: usize,
+	/// The history entry being shown, or `history.len()` for the new line
+	history_pos: usize,
+	/// The new line, kept while browsing history
+	draft: Vec<char>,
+	last_was_tab: bool
+}
+
+#[cfg(unix)]
+impl<'a> LineState<'a> {
+	fn new(prompt: &'a str, history: &'a [String], words: &'a [String]) -> LineState<'a> {
+		LineState {
+			prompt: prompt,
+			history: history,
+			words: words,
+			chars: Vec::new(),
+			cursor: 0,
+			history_pos: history.len(),
+			draft: Vec::new(),
+			last_was_tab: false
+		}
+	}
+
+	fn run(&mut self) -> io::Result<Input> {
+		loop {
+			let key = match try!(read_char()) {
+				Some(key) => key,
+				None => return Ok(Input::Eof)
+			};
+			let was_tab = self.last_was_tab;
+			self.last_was_tab = false;
+
+			match key {
+				'\r' | '\n' => return Ok(Input::Line(self.chars.iter().collect())),
+				'\x03' => {
+					print!("^C");
+					return Ok(Input::Interrupted);
+				},
+				'\x04' => {
+					if self.chars.is_empty() {
+						return Ok(Input::Eof);
+					}
+					self.delete();
+				},
+				'\t' => {
+					self.complete(was_tab);
+					self.last_was_tab = true;
+				},
+				'\x7f' | '\x08' if self.cursor > 0 => {
+					self.cursor -= 1;
+					self.delete();
+				},
+				'\x01' => self.cursor = 0,
+				'\x05' => self.cursor = self.chars.len(),
+				'\x02' => self.left(),
+				'\x06' => self.right(),
+				'\x0b' => self.chars.truncate(self.cursor),
+				'\x15' => {
+					self.chars.drain(..self.cursor);
+					self.cursor = 0;
+				},
+				'\x0c' => print!("\x1b[H\x1b[2J"),
+				'\x10' => self.history_prev(),
+				'\x0e' => self.history_next(),
+				'\x1b' => try!(self.escape()),
+				c if !c.is_control() => {
+					self.chars.insert(self.cursor, c);
+					self.cursor += 1;
+				},
+				_ => {}
+			}
+			try!(self.redraw());
+		}
+	}
+
+	/// Handles the escape sequences sent by arrow, Home, End and Delete keys.
+	fn escape(&mut self) -> io::Result<()> {
+		let mut seq = String::new();
+		if try!(read_char()) != Some('[') {
+			return Ok(());
+		}
+		while let Some(c) = try!(read_char()) {
+			seq.push(c);
+			if c.is_ascii_alphabetic() || c == '~' {
+				break;
+			}
+		}
+		match &seq[..] {
+			"A" => self.history_prev(),
+			"B" => self.history_next(),
+			"C" => self.right(),
+			"D" => self.left(),
+			"H" | "1~" => self.cursor = 0,
+			"F" | "4~" => self.cursor = self.chars.len(),
+			"3~" => self.delete(),
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn left(&mut self) {
+		if self.cursor > 0 {
+			self.cursor -= 1;
+		}
+	}
+
+	fn right(&mut self) {
+		if self.cursor < self.chars.len() {
+			self.cursor += 1;
+		}
+	}
+
+	/// Deletes the character under the cursor.
+	fn delete(&mut self) {
+		if self.cursor < self.chars.len() {
+			self.chars.remove(self.cursor);
+		}
+	}
+
+	fn history_prev(&mut self) {
+		if self.history_pos == 0 {
+			return;
+		}
+		if self.history_pos == self.history.len() {
+			self.draft = self.chars.clone();
+		}
+		self.history_pos -= 1;
+		self.chars = self.history[self.history_pos].chars().collect();
+		self.cursor = self.chars.len();
+	}
+
+	fn history_next(&mut self) {
+		if self.history_pos >= self.history.len() {
+			return;
+		}
+		self.history_pos += 1;
+		self.chars = if self.history_pos == self.history.len() {
+			self.draft.clone()
+		}
+		else {
+			self.history[self.history_pos].chars().collect()
+		};
+		self.cursor = self.chars.len();
+	}
+
+	/// Completes the identifier before the cursor, listing the candidates if Tab is pressed twice.
+	fn complete(&mut self, list: bool) {
+		let is_word = |c: char| c.is_alphanumeric() || c == '_';
+		let start = self.chars[..self.cursor].iter().rposition(|&c| !is_word(c)).map_or(0, |i| i + 1);
+		// Only names in the root table are known, not members
+		if start > 0 && self.chars[start - 1] == '.' {
+			return;
+		}
+
+		let prefix: String = self.chars[start..self.cursor].iter().collect();
+		let candidates: Vec<&String> = self.words.iter().filter(|w| w.starts_with(&prefix[..])).collect();
+		if candidates.is_empty() {
+			print!("\x07");
+			return;
+		}
+
+		let common = candidates[1..].iter().fold(&candidates[0][..], |common, word| {
+			let len = common.char_indices()
+				.zip(word.chars())
+				.take_while(|&((_, a), b)| a == b)
+				.last()
+				.map_or(0, |((i, a), _)| i + a.len_utf8());
+			&common[..len]
+		});
+		if common.len() > prefix.len() {
+			for c in common[prefix.len()..].chars() {
+				self.chars.insert(self.cursor, c);
+				self.cursor += 1;
+			}
+		}
+		else if list {
+			let names: Vec<&str> = candidates.iter().map(|w| &w[..]).collect();
+			print!("\r\n{}\r\n", names.join("  "));
+		}
+		else {
+			print!("\x07");
+		}
+	}
+
+	/// Redraws the prompt and line, placing the cursor.
+	fn redraw(&self) -> io::Result<()> {
+		let line: String = self.chars.iter().collect();
+		let mut out = format!("\r{}{}\x1b[K\r", self.prompt, line);
+		let column = self.prompt.chars().count() + self.cursor;
+		if column > 0 {
+			out.push_str(&format!("\x1b[{}C", column));
+		}
+		let stdout = io::stdout();
+		let mut stdout = stdout.lock();
+		try!(stdout.write_all(out.as_bytes()));
+		stdout.flush()
+	}
+}
+
+#[cfg(unix)]
+/// Reads one character from standard input, decoding UTF-8.
+fn read_char() -> io::Result<Option<char>> {
+	let first = match try!(read_byte()) {
+		Some(b) => b,
+		None => return Ok(None)
+	};
+	let len = match first {
+		0x00..=0x7f => return Ok(Some(first as char)),
+		0xc0..=0xdf => 2,
+		0xe0..=0xef => 3,
+		0xf0..=0xf7 => 4,
+		_ => return Ok(Some('\u{fffd}'))
+	};
+	let mut bytes = vec![first];
+	for _ in 1..len {
+		match try!(read_byte()) {
+			Some(b) => bytes.push(b),
+			None => break
+		}
+	}
+	Ok(Some(String::from_utf8(bytes).ok().and_then(|s| s.chars().next()).unwrap_or('\u{fffd}')))
+}
+
+#[cfg(unix)]
+/// Reads a byte directly from the terminal, bypassing buffering.
+fn read_byte() -> io::Result<Option<u8>> {
+	let mut byte = 0u8;
+	loop {
+		let n = unsafe { libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) };
+		match n {
+			1 => return Ok(Some(byte)),
+			0 => return Ok(None),
+			_ => {
+				let e = io::Error::last_os_error();
+				if e.kind() != io::ErrorKind::Interrupted {
+					return Err(e);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Editor;
+	#[cfg(unix)]
+	use super::LineState;
+
+	#[test]
+	fn history_skips_repeats() {
+		let mut editor = Editor {
+			history: Vec::new(),
+			history_path: None
+		};
+		editor.add_history("a");
+		editor.add_history("a");
+		editor.add_history("  ");
+		editor.add_history("b");
+		editor.add_history("a");
+		assert_eq!(editor.history, ["a", "b", "a"]);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn browse_history() {
+		let history = vec!["one".to_string(), "two".to_string()];
+		let mut state = LineState::new("> ", &history, &[]);
+		state.chars = "dra".chars().collect();
+		state.history_prev();
+		state.history_prev();
+		state.history_prev();
+		assert_eq!(state.chars.iter().collect::<String>(), "one");
+		state.history_next();
+		state.history_next();
+		assert_eq!(state.chars.iter().collect::<String>(), "dra");
+		assert_eq!(state.cursor, 3);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn edit_at_cursor() {
+		let mut state = LineState::new("> ", &[], &[]);
+		state.chars = "abc".chars().collect();
+		state.cursor = 3;
+		state.right();
+		assert_eq!(state.cursor, 3);
+		state.left();
+		state.delete();
+		assert_eq!(state.chars.iter().collect::<String>(), "ab");
+		state.delete();
+		assert_eq!(state.chars.iter().collect::<String>(), "ab");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn complete_common_prefix() {
+		let words = vec!["println".to_string(), "print".to_string(), "pow".to_string()];
+		let mut state = LineState::new("> ", &[], &words);
+		state.chars = "x = pri".chars().collect();
+		state.cursor = state.chars.len();
+		state.complete(false);
+		assert_eq!(state.chars.iter().collect::<String>(), "x = print");
+
+		// Members are not completed
+		state.chars = "a.pri".chars().collect();
+		state.cursor = state.chars.len();
+		state.complete(false);
+		assert_eq!(state.chars.iter().collect::<String>(), "a.pri");
+	}
+}
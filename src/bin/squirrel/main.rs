@@ -0,0 +1,246 @@
+//! The `squirrel` command-line interpreter.
+
+extern crate squirrel;
+extern crate libc;
+
+use squirrel::{SquirrelVM, SquirrelError, Value};
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write, Stdout, Stderr};
+use std::path::{Path, PathBuf};
+use std::process;
+
+mod editor;
+mod repl;
+
+/// The script ran, or the files compiled, without errors.
+const EXIT_SUCCESS: i32 = 0;
+/// A script failed to compile or threw an error.
+const EXIT_FAILURE: i32 = 1;
+/// The command line was invalid, or a file could not be read or written.
+const EXIT_USAGE: i32 = 2;
+
+const USAGE: &'static str = "\
+Usage:
+    squirrel run <script> [args...]      Run a script, passing args in vargv
+    squirrel check <files...>            Compile files and report errors, without running them
+    squirrel compile [-o <out>] <script> Compile a script to bytecode (default output: <script>.cnut)
+    squirrel repl                        Start an interactive session";
+
+pub type Vm = SquirrelVM<Stdout, Stderr>;
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let code = match args.first().map(|s| &s[..]) {
+		Some("run") if args.len() >= 2 => run(&args[1], &args[2..]),
+		Some("check") if args.len() >= 2 => check(&args[1..]),
+		Some("compile") => match parse_compile_args(&args[1..]) {
+			Some((input, output)) => compile(&input, &output),
+			None => usage()
+		},
+		Some("repl") if args.len() == 1 => repl::run(new_vm()),
+		Some("help") | Some("--help") | Some("-h") => {
+			println!("{}", USAGE);
+			EXIT_SUCCESS
+		},
+		_ => usage()
+	};
+	process::exit(code);
+}
+
+fn usage() -> i32 {
+	eprintln!("{}", USAGE);
+	EXIT_USAGE
+}
+
+/// Creates a virtual machine with the whole standard library and its error handler.
+pub fn new_vm() -> Vm {
+	let mut vm = SquirrelVM::new(1024, io::stdout(), io::stderr());
+	vm.set_debug_info(true);
+
+	vm.push_root_table();
+	vm.register_blob_lib().unwrap();
+	vm.register_io_lib().unwrap();
+	vm.register_system_lib().unwrap();
+	vm.register_math_lib().unwrap();
+	vm.register_string_lib().unwrap();
+	vm.pop(1);
+
+	vm.set_default_error_handler();
+	vm
+}
+
+/// Reads a file, reporting errors on standard error.
+fn read_file(path: &str) -> Option<Vec<u8>> {
+	let mut data = Vec::new();
+	match File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+		Ok(_) => Some(data),
+		Err(e) => {
+			eprintln!("error: could not read '{}': {}", path, e);
+			None
+		}
+	}
+}
+
+/// Blanks out a `#!` line so that scripts can be made executable, keeping line numbers intact.
+fn skip_shebang(data: &mut Vec<u8>) {
+	if data.starts_with(b"#!") {
+		let end = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+		data.drain(..end);
+	}
+}
+
+/// Reports an error from loading a script on standard error.
+fn report(e: &SquirrelError) {
+	match *e {
+		SquirrelError::Compile(ref e) => eprint!("{}", e.render()),
+		ref e => eprintln!("error: {}", e)
+	}
+}
+
+/// Loads a script file and pushes it as a closure.
+fn load(vm: &mut Vm, path: &str) -> Result<(), i32> {
+	let mut data = try!(read_file(path).ok_or(EXIT_USAGE));
+	skip_shebang(&mut data);
+	vm.load_bytes(&data, path).map_err(|e| {
+		report(&e);
+		EXIT_FAILURE
+	})
+}
+
+/// Runs a script with the root table as `this` and `args` as its variable arguments.
+///
+/// If the script returns an integer, it is used as the exit code.
+fn run(path: &str, args: &[String]) -> i32 {
+	let mut vm = new_vm();
+	if let Err(code) = load(&mut vm, path) {
+		return code;
+	}
+
+	vm.push_root_table();
+	for arg in args {
+		vm.push_value(&arg[..]);
+	}
+	// Runtime errors have already been reported by the error handler
+	if vm.call(args.len() as isize + 1, true, true).is_err() {
+		return EXIT_FAILURE;
+	}
+	match vm.read(-1) {
+		Ok(Value::Integer(n)) => n as i32,
+		_ => EXIT_SUCCESS
+	}
+}
+
+/// Compiles each file without running it, reporting every error found.
+fn check(paths: &[String]) -> i32 {
+	let mut vm = new_vm();
+	let mut code = EXIT_SUCCESS;
+
+	for path in paths {
+		let mut data = match read_file(path) {
+			Some(data) => data,
+			None => {
+				code = EXIT_USAGE;
+				continue;
+			}
+		};
+		skip_shebang(&mut data);
+		// Decode the script the same way as `load`, handling byte order marks and UTF-16
+		let src = match squirrel::decode_source(&data) {
+			Some(src) => src,
+			// Bytecode has already been checked by the compiler that produced it
+			None => continue
+		};
+
+		if let Err(errors) = vm.check_syntax(&src, path) {
+			for e in &errors {
+				eprint!("{}", e.render());
+			}
+			if code == EXIT_SUCCESS {
+				code = EXIT_FAILURE;
+			}
+		}
+	}
+	code
+}
+
+/// Parses `[-o <out>] <script>`, choosing an output path if none is given.
+fn parse_compile_args(args: &[String]) -> Option<(String, PathBuf)> {
+	let mut input = None;
+	let mut output = None;
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		if arg == "-o" {
+			output = match iter.next() {
+				Some(out) => Some(PathBuf::from(out)),
+				None => return None
+			};
+		}
+		else if input.is_none() {
+			input = Some(arg.clone());
+		}
+		else {
+			return None;
+		}
+	}
+
+	let input = input?;
+	let output = output.unwrap_or_else(|| Path::new(&input).with_extension("cnut"));
+	Some((input, output))
+}
+
+/// Compiles a script and writes it to `output` as bytecode.
+fn compile(input: &str, output: &Path) -> i32 {
+	let mut vm = new_vm();
+	if let Err(code) = load(&mut vm, input) {
+		return code;
+	}
+
+	let mut bytecode = Vec::new();
+	if let Err(e) = vm.write_closure(&mut bytecode) {
+		report(&e);
+		return EXIT_FAILURE;
+	}
+	match File::create(output).and_then(|mut f| f.write_all(&bytecode)) {
+		Ok(()) => EXIT_SUCCESS,
+		Err(e) => {
+			eprintln!("error: could not write '{}': {}", output.display(), e);
+			EXIT_USAGE
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{skip_shebang, parse_compile_args};
+	use std::path::PathBuf;
+
+	fn args(list: &[&str]) -> Vec<String> {
+		list.iter().map(|s| s.to_string()).collect()
+	}
+
+	#[test]
+	fn shebang_keeps_lines() {
+		let mut data = b"#!/usr/bin/env squirrel\nprint(1)".to_vec();
+		skip_shebang(&mut data);
+		assert_eq!(data, b"\nprint(1)");
+
+		let mut plain = b"print(1)".to_vec();
+		skip_shebang(&mut plain);
+		assert_eq!(plain, b"print(1)");
+
+		let mut only = b"#!squirrel".to_vec();
+		skip_shebang(&mut only);
+		assert!(only.is_empty());
+	}
+
+	#[test]
+	fn compile_args() {
+		assert_eq!(parse_compile_args(&args(&["a.nut"])), Some(("a.nut".to_string(), PathBuf::from("a.cnut"))));
+		assert_eq!(parse_compile_args(&args(&["-o", "out.bin", "a.nut"])), Some(("a.nut".to_string(), PathBuf::from("out.bin"))));
+		assert_eq!(parse_compile_args(&args(&["a.nut", "-o", "out.bin"])), Some(("a.nut".to_string(), PathBuf::from("out.bin"))));
+		assert_eq!(parse_compile_args(&args(&["a.nut", "-o"])), None);
+		assert_eq!(parse_compile_args(&args(&["a.nut", "b.nut"])), None);
+		assert_eq!(parse_compile_args(&args(&[])), None);
+	}
+}
@@ -0,0 +1,216 @@
+//! The interactive session started by `squirrel repl`.
+
+use squirrel::{CompilerError, InspectOptions, SquirrelError, Value};
+
+use editor::{Editor, Input};
+use Vm;
+
+const HELP: &'static str = "\
+Enter statements or expressions. The values of expressions are printed.
+Input continues onto more lines until it is complete; enter an empty line to stop early.
+Locals only last for one input, so use `name <- value` to keep a value.
+
+Commands:
+    :load <file>   Run a script file
+    :type <expr>   Show the type of an expression
+    :gc            Run the garbage collector
+    :help          Show this message
+    :quit          Leave the session (or press Ctrl-D)";
+
+/// Whether the session should carry on after a command.
+enum Flow {
+	Continue,
+	Quit
+}
+
+/// Reads input until `:quit` or the end of input, running it as it is completed.
+pub fn run(mut vm: Vm) -> i32 {
+	// Call stacks are more noise than help for one-line inputs, but thrown tables and instances are worth expanding
+	vm.new_closure(|vm: &mut Vm| {
+		match vm.read(2) {
+			Ok(Value::String(msg)) => eprintln!("error: {}", msg),
+			_ => eprintln!("error: {}", vm.inspect(2, InspectOptions::default().max_depth(2)))
+		}
+	}, 0);
+	vm.set_error_handler();
+
+	let mut editor = Editor::new();
+	let mut buffer = String::new();
+	loop {
+		let prompt = if buffer.is_empty() { "> " } else { "... " };
+		let words = root_keys(&mut vm);
+		let line = match editor.read_line(prompt, &words) {
+			Ok(Input::Line(line)) => line,
+			Ok(Input::Interrupted) => {
+				buffer.clear();
+				continue;
+			},
+			Ok(Input::Eof) | Err(_) => break
+		};
+		editor.add_history(&line);
+
+		if buffer.is_empty() {
+			let trimmed = line.trim();
+			if trimmed.is_empty() {
+				continue;
+			}
+			if trimmed.starts_with(':') || trimmed == "quit" {
+				match command(&mut vm, trimmed) {
+					Flow::Continue => continue,
+					Flow::Quit => break
+				}
+			}
+		}
+		else {
+			buffer.push('\n');
+		}
+		buffer.push_str(&line);
+
+		// An empty line forces incomplete input to be reported
+		if eval(&mut vm, &buffer, line.trim().is_empty()) {
+			buffer.clear();
+		}
+	}
+	editor.save_history();
+	0
+}
+
+/// Runs a meta-command.
+fn command(vm: &mut Vm, input: &str) -> Flow {
+	let (name, arg) = match input.find(char::is_whitespace) {
+		Some(i) => (&input[..i], input[i..].trim()),
+		None => (input, "")
+	};
+	match name {
+		":quit" | ":q" | "quit" => return Flow::Quit,
+		":help" | ":h" => println!("{}", HELP),
+		":load" | ":l" if !arg.is_empty() => load(vm, arg),
+		":type" | ":t" if !arg.is_empty() => {
+			if let Some(Value::String(ty)) = evaluate(vm, &format!("typeof ({})", arg)) {
+				println!("{}", ty);
+			}
+		},
+		":gc" => match vm.collect_garbage() {
+			n if n < 0 => println!("the garbage collector is not available"),
+			n => println!("collected {} objects", n)
+		},
+		_ => eprintln!("unknown command '{}', enter :help for a list", input)
+	}
+	Flow::Continue
+}
+
+/// Runs a script file with the root table as `this`.
+fn load(vm: &mut Vm, path: &str) {
+	let top = vm.get_top();
+	match vm.load_file(path) {
+		Ok(()) => {
+			vm.push_root_table();
+			let _ = vm.call(1, false, true);
+		},
+		Err(SquirrelError::Compile(e)) => eprint!("{}", e.render()),
+		Err(e) => eprintln!("error: {}", e)
+	}
+	vm.set_top(top);
+}
+
+/// Evaluates an expression, returning `None` if it does not compile or throws.
+fn evaluate(vm: &mut Vm, expr: &str) -> Option<Value> {
+	let top = vm.get_top();
+	let result = match vm.compile_str(&format!("return ({});", expr), "repl") {
+		Ok(()) => {
+			vm.push_root_table();
+			match vm.call(1, true, true) {
+				Ok(()) => vm.read(-1).ok(),
+				Err(()) => None
+			}
+		},
+		Err(e) => {
+			eprint!("{}", e.render());
+			None
+		}
+	};
+	vm.set_top(top);
+	result
+}
+
+/// Runs the input, printing the value if it is an expression.
+///
+/// Returns `false` if the input is incomplete and more lines should be read.
+fn eval(vm: &mut Vm, src: &str, force: bool) -> bool {
+	let top = vm.get_top();
+	let expr = src.trim().trim_end_matches(';');
+
+	// Anything that compiles as an expression is evaluated as one
+	if vm.compile_str(&format!("return ({});", expr), "repl").is_ok() {
+		vm.push_root_table();
+		if vm.call(1, true, true).is_ok() {
+			match vm.read(-1) {
+				Ok(Value::Null) | Err(_) => {},
+				Ok(_) => println!("{}", vm.inspect(-1, InspectOptions::default()))
+			}
+		}
+		vm.set_top(top);
+		return true;
+	}
+
+	match vm.compile_str(src, "repl") {
+		Ok(()) => {
+			vm.push_root_table();
+			let _ = vm.call(1, false, true);
+		},
+		Err(ref e) if !force && is_incomplete(e, src) => return false,
+		Err(e) => eprint!("{}", e.render())
+	}
+	vm.set_top(top);
+	true
+}
+
+/// Checks whether a compiler error was caused by reaching the end of the input,
+/// such as an unclosed brace or string, rather than by a mistake in it.
+fn is_incomplete(e: &CompilerError, src: &str) -> bool {
+	let lines: Vec<&str> = src.split('\n').collect();
+	let last = lines.len() as isize;
+	let last_len = lines[lines.len() - 1].trim_end().chars().count() as isize;
+	e.line > last || (e.line == last && e.column > last_len)
+}
+
+/// Gets the names in the root table, for completion.
+fn root_keys(vm: &mut Vm) -> Vec<String> {
+	let mut vm = vm.guard();
+	vm.push_root_table();
+	vm.push_null();
+	let mut keys = Vec::new();
+	while vm.next(-2).is_ok() {
+		if let Ok(key) = vm.read::<String>(-2) {
+			keys.push(key);
+		}
+		vm.pop(2);
+	}
+	keys.sort();
+	keys
+}
+
+#[cfg(test)]
+mod tests {
+	use super::is_incomplete;
+	use squirrel::CompilerError;
+
+	fn error(line: isize, column: isize) -> CompilerError {
+		CompilerError {
+			desc: "expected '}'".to_string(),
+			source: "repl".to_string(),
+			line: line,
+			column: column,
+			line_text: None
+		}
+	}
+
+	#[test]
+	fn incomplete_input() {
+		let src = "function f() {\n\tlocal a = 1;";
+		assert!(is_incomplete(&error(2, 14), src));
+		assert!(is_incomplete(&error(3, 1), src));
+		assert!(!is_incomplete(&error(2, 5), src));
+		assert!(!is_incomplete(&error(1, 3), src));
+	}
+}
@@ -0,0 +1,431 @@
+//! Exposing Rust types to scripts as classes, with operators mapped to metamethods.
+
+use ffi;
+use std::cmp::Ordering;
+use std::fmt::Display;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use {SquirrelVM, SquirrelError, FromSquirrel, ToSquirrel, NativeReturn, RawReturn, ThrowError};
+
+/// The registry table holding the class of each registered type, by name.
+const CLASSES_KEY: &'static str = "squirrel.classes";
+
+/// A pusher for one member of a class.
+type Member<P, E> = Box<dyn FnOnce(&mut SquirrelVM<P, E>)>;
+
+/// One right hand operand type of a binary operator, which pushes the result and returns `true` if the operand
+/// at index 2 can be read as that type.
+///
+/// It takes the raw VM so that it does not need `P` and `E` to be `'static` to be boxed.
+type Operand<T> = Box<dyn Fn(&T, ffi::HSQUIRRELVM) -> bool>;
+
+/// A Rust type that can be registered as a Squirrel class with `ClassBuilder`.
+///
+/// Once registered, values of the type are pushed as new instances of the class, and instances are read back
+/// by cloning the value they hold.
+pub trait SquirrelClass: Sized + 'static {
+	/// Gets the name of the class, which it is registered under in the root table.
+	fn class_name() -> &'static str;
+}
+
+/// Builds the class for a Rust type, whose instances each hold a value of the type.
+///
+/// Operators are opted into one at a time, each adding the metamethod Squirrel calls for it.
+/// Right hand operands are converted with `FromSquirrel`, and results with `ToSquirrel`. An operator can be added
+/// for several right hand types, which are tried in the order they were added.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # use std::ops::{Add, Mul};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// #[derive(Clone, PartialEq, PartialOrd)]
+/// struct Vec2 { x: f64, y: f64 }
+///
+/// impl SquirrelClass for Vec2 {
+/// 	fn class_name() -> &'static str { "Vec2" }
+/// }
+/// # impl Add for Vec2 {
+/// # 	type Output = Vec2;
+/// # 	fn add(self, o: Vec2) -> Vec2 { Vec2 { x: self.x + o.x, y: self.y + o.y } }
+/// # }
+/// # impl Mul<f64> for Vec2 {
+/// # 	type Output = Vec2;
+/// # 	fn mul(self, k: f64) -> Vec2 { Vec2 { x: self.x * k, y: self.y * k } }
+/// # }
+///
+/// ClassBuilder::<_, _, Vec2>::new()
+/// 	.constructor(|vm| -> Result<Vec2, SquirrelError> {
+/// 		Ok(Vec2 { x: try!(vm.read(2)), y: try!(vm.read(3)) })
+/// 	})
+/// 	.add::<Vec2>()
+/// 	.mul::<f64>()
+/// 	.register(&mut vm)
+/// 	.unwrap();
+/// // Scripts can now evaluate `Vec2(1, 2) + Vec2(3, 4) * 2.0`
+/// ```
+pub struct ClassBuilder<P, E, T> {
+	members: Vec<(String, Member<P, E>)>,
+	operators: Vec<(&'static str, Vec<Operand<T>>)>,
+	marker: PhantomData<fn() -> T>
+}
+
+impl<P: Write + Sync, E: Write + Sync, T: SquirrelClass> ClassBuilder<P, E, T> {
+	/// Creates a builder for a class with no members.
+	pub fn new() -> ClassBuilder<P, E, T> {
+		ClassBuilder {
+			members: Vec::new(),
+			operators: Vec::new(),
+			marker: PhantomData
+		}
+	}
+
+	/// Sets the constructor scripts call to create instances, which receives the arguments from index 2.
+	///
+	/// Without a constructor, instances can only be created by pushing values from Rust.
+	/// Scripts can call the constructor directly, such as with `Vec2.constructor.call(other, 1, 2)`, so it fails
+	/// unless `this` is an instance of the class that does not hold a value yet.
+	pub fn constructor<F, Err>(self, func: F) -> ClassBuilder<P, E, T>
+		where F: Fn(&mut SquirrelVM<P, E>) -> Result<T, Err> + 'static, Err: ThrowError
+	{
+		self.member("constructor", move |vm: &mut SquirrelVM<P, E>| {
+			if let Err(e) = unsafe { check_unconstructed::<T>(vm.0, 1) } {
+				return RawReturn(e.throw(vm));
+			}
+			match func(vm) {
+				Ok(value) => {
+					unsafe { set_value(vm.0, 1, value); }
+					RawReturn(0)
+				},
+				Err(e) => RawReturn(e.throw(vm))
+			}
+		})
+	}
+
+	/// Adds a method, which is called with the value held by `this` and the arguments from index 2.
+	pub fn method<F, R>(self, name: &str, func: F) -> ClassBuilder<P, E, T>
+		where F: Fn(&T, &mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.member(name, move |vm: &mut SquirrelVM<P, E>| -> Result<R, SquirrelError> {
+			let this = try!(unsafe { value_ptr::<T>(vm.0, 1) });
+			Ok(func(unsafe { &*this }, vm))
+		})
+	}
+
+	/// Implements `+` with `_add`.
+	pub fn add<Rhs>(self) -> ClassBuilder<P, E, T>
+		where T: Add<Rhs> + Clone, Rhs: FromSquirrel + 'static, T::Output: ToSquirrel
+	{
+		self.binary("_add", |a: T, b: Rhs| a + b)
+	}
+
+	/// Implements `-` with `_sub`.
+	pub fn sub<Rhs>(self) -> ClassBuilder<P, E, T>
+		where T: Sub<Rhs> + Clone, Rhs: FromSquirrel + 'static, T::Output: ToSquirrel
+	{
+		self.binary("_sub", |a: T, b: Rhs| a - b)
+	}
+
+	/// Implements `*` with `_mul`.
+	pub fn mul<Rhs>(self) -> ClassBuilder<P, E, T>
+		where T: Mul<Rhs> + Clone, Rhs: FromSquirrel + 'static, T::Output: ToSquirrel
+	{
+		self.binary("_mul", |a: T, b: Rhs| a * b)
+	}
+
+	/// Implements `/` with `_div`.
+	pub fn div<Rhs>(self) -> ClassBuilder<P, E, T>
+		where T: Div<Rhs> + Clone, Rhs: FromSquirrel + 'static, T::Output: ToSquirrel
+	{
+		self.binary("_div", |a: T, b: Rhs| a / b)
+	}
+
+	/// Implements `%` with `_modulo`.
+	pub fn rem<Rhs>(self) -> ClassBuilder<P, E, T>
+		where T: Rem<Rhs> + Clone, Rhs: FromSquirrel + 'static, T::Output: ToSquirrel
+	{
+		self.binary("_modulo", |a: T, b: Rhs| a % b)
+	}
+
+	/// Implements unary `-` with `_unm`.
+	pub fn neg(self) -> ClassBuilder<P, E, T>
+		where T: Neg + Clone, T::Output: ToSquirrel
+	{
+		self.member("_unm", |vm: &mut SquirrelVM<P, E>| -> Result<T::Output, SquirrelError> {
+			let this = try!(unsafe { value_ptr::<T>(vm.0, 1) });
+			let this = unsafe { (*this).clone() };
+			Ok(-this)
+		})
+	}
+
+	/// Implements `<`, `>`, `<=`, `>=` and `<=>` between instances with `_cmp`.
+	pub fn ord(self) -> ClassBuilder<P, E, T>
+		where T: Ord + Clone
+	{
+		self.member("_cmp", |vm: &mut SquirrelVM<P, E>| -> Result<isize, SquirrelError> {
+			let this = try!(unsafe { value_ptr::<T>(vm.0, 1) });
+			let other: T = try!(vm.read(2));
+			Ok(match unsafe { (*this).cmp(&other) } {
+				Ordering::Less => -1,
+				Ordering::Equal => 0,
+				Ordering::Greater => 1
+			})
+		})
+	}
+
+	/// Implements `tostring()` with `_tostring`.
+	pub fn display(self) -> ClassBuilder<P, E, T>
+		where T: Display
+	{
+		self.member("_tostring", |vm: &mut SquirrelVM<P, E>| -> Result<String, SquirrelError> {
+			let this = try!(unsafe { value_ptr::<T>(vm.0, 1) });
+			Ok(unsafe { (*this).to_string() })
+		})
+	}
+
+	/// Creates the class and stores it in the root table, replacing any class registered under the same name.
+	pub fn register(self, vm: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError> {
+		let mut vm = vm.guard();
+		vm.push_root_table();
+		vm.push_value(T::class_name());
+		unsafe { ffi::sq_newclass(vm.0, 0); }
+		for (name, push_member) in self.members {
+			vm.push_value(&name[..]);
+			push_member(&mut vm);
+			if vm.new_slot(-3, false).is_err() {
+				return Err(SquirrelError::Runtime(vm.take_last_error()));
+			}
+		}
+		for (name, operands) in self.operators {
+			vm.push_value(name);
+			vm.new_closure(move |vm: &mut SquirrelVM<P, E>| -> Result<RawReturn, SquirrelError> {
+				let this = try!(unsafe { value_ptr::<T>(vm.0, 1) });
+				for operand in &operands {
+					if operand(unsafe { &*this }, vm.0) {
+						return Ok(RawReturn(1));
+					}
+				}
+				let t = unsafe { ffi::sq_gettype(vm.0, 2) };
+				Err(SquirrelError::Runtime(format!("{} does not support {} with {}", T::class_name(), name, ::convert::type_name(t))))
+			}, 0);
+			if vm.new_slot(-3, false).is_err() {
+				return Err(SquirrelError::Runtime(vm.take_last_error()));
+			}
+		}
+		let class = vm.handle(-1);
+		if vm.new_slot(-3, false).is_err() {
+			return Err(SquirrelError::Runtime(vm.take_last_error()));
+		}
+
+		vm.push_registry_entry(CLASSES_KEY);
+		vm.push_value(T::class_name());
+		vm.push_handle(&class);
+		let _ = vm.raw_set(-3);
+		Ok(())
+	}
+
+	fn member<F, R>(mut self, name: &str, func: F) -> ClassBuilder<P, E, T>
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.members.retain(|(n, _)| n != name);
+		self.operators.retain(|&(n, _)| n != name);
+		self.members.push((name.to_string(), Box::new(move |vm: &mut SquirrelVM<P, E>| vm.new_closure(func, 0))));
+		self
+	}
+
+	/// Adds a right hand operand type to a binary operator metamethod, which reads it from index 2.
+	fn binary<Rhs, O, F>(mut self, name: &'static str, op: F) -> ClassBuilder<P, E, T>
+		where T: Clone, Rhs: FromSquirrel + 'static, O: ToSquirrel, F: Fn(T, Rhs) -> O + 'static
+	{
+		let operand: Operand<T> = Box::new(move |this: &T, v: ffi::HSQUIRRELVM| {
+			let mut vm = ManuallyDrop::new(SquirrelVM::<P, E>(v, PhantomData));
+			match vm.read::<Rhs>(2) {
+				Ok(rhs) => {
+					vm.push_value(op(this.clone(), rhs));
+					true
+				},
+				Err(_) => false
+			}
+		});
+		self.members.retain(|(n, _)| n != name);
+		match self.operators.iter().position(|&(n, _)| n == name) {
+			Some(i) => self.operators[i].1.push(operand),
+			None => self.operators.push((name, vec![operand]))
+		}
+		self
+	}
+}
+
+impl<P: Write + Sync, E: Write + Sync, T: SquirrelClass> Default for ClassBuilder<P, E, T> {
+	fn default() -> ClassBuilder<P, E, T> {
+		ClassBuilder::new()
+	}
+}
+
+impl<T: SquirrelClass> ToSquirrel for T {
+	/// Pushes a new instance holding the value, without calling the constructor.
+	///
+	/// `null` is pushed if the class has not been registered.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		unsafe {
+			if !push_class(vm.0, T::class_name()) {
+				vm.push_null();
+				return;
+			}
+			ffi::sq_createinstance(vm.0, -1);
+			ffi::sq_remove(vm.0, -2);
+			set_value(vm.0, -1, self);
+		}
+	}
+}
+
+impl<T: SquirrelClass + Clone> FromSquirrel for T {
+	/// Reads a copy of the value held by an instance of the class, or of a class derived from it.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<T, SquirrelError> {
+		unsafe { value_ptr::<T>(vm.0, idx).map(|p| (*p).clone()) }
+	}
+}
+
+/// Pushes the class registered under `name`, returning `false` if there is none.
+unsafe fn push_class(v: ffi::HSQUIRRELVM, name: &str) -> bool {
+	let top = ffi::sq_gettop(v);
+	ffi::sq_pushregistrytable(v);
+	ffi::sq_pushstring(v, CLASSES_KEY.as_ptr() as *const ffi::SQChar, CLASSES_KEY.len() as ffi::SQInteger);
+	if ffi::SQ_SUCCEEDED(ffi::sq_rawget(v, -2)) {
+		ffi::sq_pushstring(v, name.as_ptr() as *const ffi::SQChar, name.len() as ffi::SQInteger);
+		if ffi::SQ_SUCCEEDED(ffi::sq_rawget(v, -2)) {
+			// Leave only the class, removing the registry and the table of classes
+			ffi::sq_remove(v, -2);
+			ffi::sq_remove(v, -2);
+			return true;
+		}
+	}
+	ffi::sq_settop(v, top);
+	false
+}
+
+/// Checks that the value at `idx` is an instance of the class for `T`, returning its absolute index.
+unsafe fn check_instance<T: SquirrelClass>(v: ffi::HSQUIRRELVM, idx: isize) -> Result<isize, SquirrelError> {
+	let name = T::class_name();
+	if ffi::sq_gettype(v, idx) != ffi::SQObjectType::OT_INSTANCE {
+		return Err(::convert::mismatch(v, idx, "instance"));
+	}
+	let idx = if idx < 0 { ffi::sq_gettop(v) + idx + 1 } else { idx };
+	ffi::sq_push(v, idx);
+	if !push_class(v, name) {
+		ffi::sq_poptop(v);
+		return Err(SquirrelError::Runtime(format!("class '{}' has not been registered", name)));
+	}
+	let is_instance = ffi::sq_instanceof(v) != 0;
+	ffi::sq_pop(v, 2);
+	if !is_instance {
+		return Err(SquirrelError::Runtime(format!("expected an instance of {} at index {}", name, idx)));
+	}
+	Ok(idx)
+}
+
+/// Checks that the value at `idx` is an instance of the class for `T` that does not hold a value yet.
+///
+/// Methods may be using the value an instance holds, so it must never be replaced.
+unsafe fn check_unconstructed<T: SquirrelClass>(v: ffi::HSQUIRRELVM, idx: isize) -> Result<(), SquirrelError> {
+	let idx = try!(check_instance::<T>(v, idx));
+	let mut p = ptr::null_mut();
+	ffi::sq_getinstanceup(v, idx, &mut p, ptr::null_mut());
+	if !p.is_null() {
+		return Err(SquirrelError::Runtime(format!("the instance of {} at index {} has already been constructed", T::class_name(), idx)));
+	}
+	Ok(())
+}
+
+/// Gets the value held by the instance at `idx`, checking that it is an instance of the class for `T`.
+unsafe fn value_ptr<T: SquirrelClass>(v: ffi::HSQUIRRELVM, idx: isize) -> Result<*mut T, SquirrelError> {
+	let name = T::class_name();
+	let idx = try!(check_instance::<T>(v, idx));
+	let mut p = ptr::null_mut();
+	ffi::sq_getinstanceup(v, idx, &mut p, ptr::null_mut());
+	if p.is_null() {
+		return Err(SquirrelError::Runtime(format!("the instance of {} at index {} has not been constructed", name, idx)));
+	}
+	Ok(p as *mut T)
+}
+
+/// Stores a value in the instance at `idx`, dropping any value it already held.
+unsafe fn set_value<T: 'static>(v: ffi::HSQUIRRELVM, idx: isize, value: T) {
+	let mut old = ptr::null_mut();
+	ffi::sq_getinstanceup(v, idx, &mut old, ptr::null_mut());
+	ffi::sq_setinstanceup(v, idx, Box::into_raw(Box::new(value)) as ffi::SQUserPointer);
+	ffi::sq_setreleasehook(v, idx, release_value::<T>);
+	if !old.is_null() {
+		release_value::<T>(old, 0);
+	}
+}
+
+/// Frees the value held by an instance when it is collected.
+extern fn release_value<T>(p: ffi::SQUserPointer, _: ffi::SQInteger) -> ffi::SQInteger {
+	// There is nowhere to report a panic raised while dropping the value
+	let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+		if !p.is_null() {
+			unsafe { drop(Box::from_raw(p as *mut T)); }
+		}
+	}));
+	1
+}
+
+#[cfg(test)]
+mod tests {
+	use std::ops::Mul;
+
+	use super::{ClassBuilder, SquirrelClass};
+
+	#[derive(Clone)]
+	struct Vec2(f64, f64);
+
+	impl SquirrelClass for Vec2 {
+		fn class_name() -> &'static str { "Vec2" }
+	}
+
+	impl Mul<Vec2> for Vec2 {
+		type Output = f64;
+
+		fn mul(self, other: Vec2) -> f64 {
+			self.0 * other.0 + self.1 * other.1
+		}
+	}
+
+	impl Mul<f64> for Vec2 {
+		type Output = Vec2;
+
+		fn mul(self, k: f64) -> Vec2 {
+			Vec2(self.0 * k, self.1 * k)
+		}
+	}
+
+	type Builder = ClassBuilder<Vec<u8>, Vec<u8>, Vec2>;
+
+	fn operands(builder: &Builder, name: &str) -> usize {
+		builder.operators.iter().find(|&&(n, _)| n == name).map_or(0, |(_, operands)| operands.len())
+	}
+
+	#[test]
+	fn operand_types_accumulate() {
+		let builder = Builder::new().mul::<Vec2>().mul::<f64>();
+		assert_eq!(operands(&builder, "_mul"), 2);
+		assert!(builder.members.is_empty());
+	}
+
+	#[test]
+	fn methods_replace_operators() {
+		let builder = Builder::new().mul::<f64>().method("_mul", |_, _| ());
+		assert_eq!(operands(&builder, "_mul"), 0);
+		assert_eq!(builder.members.len(), 1);
+
+		let builder = builder.mul::<Vec2>();
+		assert_eq!(operands(&builder, "_mul"), 1);
+		assert!(builder.members.is_empty());
+	}
+}
@@ -0,0 +1,423 @@
+//! Conversions between Rust values and values on a Squirrel stack.
+
+use ffi;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hash};
+use std::io::Write;
+use std::{ptr, slice};
+use std::str::from_utf8;
+
+use {SquirrelVM, SquirrelError};
+
+/// A value that can be pushed onto the stack of a virtual machine.
+pub trait ToSquirrel {
+	/// Pushes the value onto the top of the stack.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>);
+}
+
+/// A value that can be read from the stack of a virtual machine.
+pub trait FromSquirrel: Sized {
+	/// Reads the value stored at `idx` without popping it.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<Self, SquirrelError>;
+}
+
+/// Gets the script visible name of a type, as returned by `typeof`.
+pub fn type_name(t: ffi::SQObjectType) -> &'static str {
+	match t {
+		ffi::SQObjectType::OT_NULL => "null",
+		ffi::SQObjectType::OT_INTEGER => "integer",
+		ffi::SQObjectType::OT_FLOAT => "float",
+		ffi::SQObjectType::OT_BOOL => "bool",
+		ffi::SQObjectType::OT_STRING => "string",
+		ffi::SQObjectType::OT_TABLE => "table",
+		ffi::SQObjectType::OT_ARRAY => "array",
+		ffi::SQObjectType::OT_USERDATA => "userdata",
+		ffi::SQObjectType::OT_CLOSURE => "function",
+		ffi::SQObjectType::OT_NATIVECLOSURE => "function",
+		ffi::SQObjectType::OT_GENERATOR => "generator",
+		ffi::SQObjectType::OT_USERPOINTER => "userpointer",
+		ffi::SQObjectType::OT_THREAD => "thread",
+		ffi::SQObjectType::OT_FUNCPROTO => "function",
+		ffi::SQObjectType::OT_CLASS => "class",
+		ffi::SQObjectType::OT_INSTANCE => "instance",
+		ffi::SQObjectType::OT_WEAKREF => "weakref",
+		ffi::SQObjectType::OT_OUTER => "outer"
+	}
+}
+
+/// Builds a type mismatch error for the value stored at `idx`.
+pub fn mismatch(v: ffi::HSQUIRRELVM, idx: isize, expected: &'static str) -> SquirrelError {
+	SquirrelError::Type {
+		idx: idx,
+		expected: expected,
+		found: type_name(unsafe { ffi::sq_gettype(v, idx) })
+	}
+}
+
+/// Converts a script integer to a narrower Rust integer, failing instead of wrapping when it is out of range.
+fn narrow<T: TryFrom<ffi::SQInteger>>(i: ffi::SQInteger, idx: isize, name: &str) -> Result<T, SquirrelError> {
+	T::try_from(i).map_err(|_| SquirrelError::Runtime(format!("integer {} at index {} is out of range for {}", i, idx, name)))
+}
+
+macro_rules! integer_conversions {
+	($($t:ty),*) => {
+		$(
+			impl ToSquirrel for $t {
+				fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+					vm.push_integer(self as ffi::SQInteger);
+				}
+			}
+
+			impl FromSquirrel for $t {
+				fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<$t, SquirrelError> {
+					let mut i = 0;
+					if ffi::SQ_SUCCEEDED(unsafe { ffi::sq_getinteger(vm.0, idx, &mut i) }) {
+						narrow(i, idx, stringify!($t))
+					}
+					else {
+						Err(mismatch(vm.0, idx, "integer"))
+					}
+				}
+			}
+		)*
+	}
+}
+
+integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+macro_rules! float_conversions {
+	($($t:ty),*) => {
+		$(
+			impl ToSquirrel for $t {
+				fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+					vm.push_float(self as ffi::SQFloat);
+				}
+			}
+
+			impl FromSquirrel for $t {
+				fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<$t, SquirrelError> {
+					let mut f = 0.0;
+					if ffi::SQ_SUCCEEDED(unsafe { ffi::sq_getfloat(vm.0, idx, &mut f) }) {
+						Ok(f as $t)
+					}
+					else {
+						Err(mismatch(vm.0, idx, "float"))
+					}
+				}
+			}
+		)*
+	}
+}
+
+float_conversions!(f32, f64);
+
+impl ToSquirrel for bool {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		vm.push_bool(self);
+	}
+}
+
+impl FromSquirrel for bool {
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<bool, SquirrelError> {
+		let mut b = 0;
+		if ffi::SQ_SUCCEEDED(unsafe { ffi::sq_getbool(vm.0, idx, &mut b) }) {
+			Ok(b != 0)
+		}
+		else {
+			Err(mismatch(vm.0, idx, "bool"))
+		}
+	}
+}
+
+impl<'a> ToSquirrel for &'a str {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		// Push with an explicit length so interior nulls survive
+		unsafe { ffi::sq_pushstring(vm.0, self.as_ptr() as *const ffi::SQChar, self.len() as ffi::SQInteger); }
+	}
+}
+
+impl ToSquirrel for String {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		(&self[..]).push_to(vm);
+	}
+}
+
+impl FromSquirrel for String {
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<String, SquirrelError> {
+		let mut s = ptr::null();
+		if ffi::SQ_SUCCEEDED(unsafe { ffi::sq_getstring(vm.0, idx, &mut s) }) {
+			// Strings may contain nulls, so use the stored length
+			let len = unsafe { ffi::sq_getsize(vm.0, idx) };
+			let bytes = unsafe { slice::from_raw_parts(s as *const u8, len as usize) };
+			match from_utf8(bytes) {
+				Ok(s) => Ok(s.to_string()),
+				Err(_) => Err(SquirrelError::Runtime(format!("string at index {} is not valid UTF-8", idx)))
+			}
+		}
+		else {
+			Err(mismatch(vm.0, idx, "string"))
+		}
+	}
+}
+
+impl<T: ToSquirrel> ToSquirrel for Option<T> {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		match self {
+			Some(t) => t.push_to(vm),
+			None => vm.push_null()
+		}
+	}
+}
+
+impl<T: FromSquirrel> FromSquirrel for Option<T> {
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<Option<T>, SquirrelError> {
+		match unsafe { ffi::sq_gettype(vm.0, idx) } {
+			ffi::SQObjectType::OT_NULL => Ok(None),
+			_ => T::read(vm, idx).map(Some)
+		}
+	}
+}
+
+impl FromSquirrel for () {
+	fn read<P: Write + Sync, E: Write + Sync>(_: &SquirrelVM<P, E>, _: isize) -> Result<(), SquirrelError> {
+		Ok(())
+	}
+}
+
+/* Collections */
+
+/// Converts a relative index to an absolute one, so that it stays valid as values are pushed.
+fn absolute(v: ffi::HSQUIRRELVM, idx: isize) -> isize {
+	if idx < 0 { unsafe { ffi::sq_gettop(v) + idx + 1 } } else { idx }
+}
+
+/// Pushes a new array holding the items, storing each by index rather than appending.
+fn push_array<P, E, I>(vm: &mut SquirrelVM<P, E>, len: usize, items: I)
+	where P: Write + Sync, E: Write + Sync, I: Iterator, I::Item: ToSquirrel
+{
+	vm.new_array(len);
+	for (i, item) in items.enumerate() {
+		vm.push_integer(i as ffi::SQInteger);
+		item.push_to(vm);
+		let _ = vm.raw_set(-3);
+	}
+}
+
+/// Pushes a new table holding the pairs, with room for all of them.
+fn push_table<P, E, I, K, V>(vm: &mut SquirrelVM<P, E>, len: usize, pairs: I)
+	where P: Write + Sync, E: Write + Sync, I: Iterator<Item = (K, V)>, K: ToSquirrel, V: ToSquirrel
+{
+	vm.new_table_with_capacity(len);
+	for (key, value) in pairs {
+		key.push_to(vm);
+		value.push_to(vm);
+		// Only null keys are rejected, and the pair is popped either way
+		let _ = vm.raw_set(-3);
+	}
+}
+
+/// Reads each element of the array at `idx` in order, without calling metamethods.
+fn read_array<P, E, F>(vm: &SquirrelVM<P, E>, idx: isize, mut f: F) -> Result<(), SquirrelError>
+	where P: Write + Sync, E: Write + Sync, F: FnMut(&SquirrelVM<P, E>, usize) -> Result<(), SquirrelError>
+{
+	if unsafe { ffi::sq_gettype(vm.0, idx) } != ffi::SQObjectType::OT_ARRAY {
+		return Err(mismatch(vm.0, idx, "array"));
+	}
+	let idx = absolute(vm.0, idx);
+	let len = unsafe { ffi::sq_getsize(vm.0, idx) } as usize;
+	let top = unsafe { ffi::sq_gettop(vm.0) };
+	let mut result = Ok(());
+	for i in 0..len {
+		unsafe {
+			ffi::sq_pushinteger(vm.0, i as ffi::SQInteger);
+			ffi::sq_rawget(vm.0, idx);
+		}
+		result = f(vm, i);
+		unsafe { ffi::sq_settop(vm.0, top); }
+		if result.is_err() {
+			break;
+		}
+	}
+	result
+}
+
+/// Reads each key and value of the table at `idx`, without calling metamethods.
+fn read_table<P, E, F>(vm: &SquirrelVM<P, E>, idx: isize, mut f: F) -> Result<(), SquirrelError>
+	where P: Write + Sync, E: Write + Sync, F: FnMut(&SquirrelVM<P, E>) -> Result<(), SquirrelError>
+{
+	if unsafe { ffi::sq_gettype(vm.0, idx) } != ffi::SQObjectType::OT_TABLE {
+		return Err(mismatch(vm.0, idx, "table"));
+	}
+	let idx = absolute(vm.0, idx);
+	let top = unsafe { ffi::sq_gettop(vm.0) };
+	let mut result = Ok(());
+	unsafe { ffi::sq_pushnull(vm.0); }
+	while ffi::SQ_SUCCEEDED(unsafe { ffi::sq_next(vm.0, idx) }) {
+		result = f(vm);
+		unsafe { ffi::sq_pop(vm.0, 2); }
+		if result.is_err() {
+			break;
+		}
+	}
+	unsafe { ffi::sq_settop(vm.0, top); }
+	result
+}
+
+/// Gets the number of slots in the container at `idx`.
+fn slot_count<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> usize {
+	match unsafe { ffi::sq_getsize(vm.0, idx) } {
+		n if n > 0 => n as usize,
+		_ => 0
+	}
+}
+
+impl<T: ToSquirrel> ToSquirrel for Vec<T> {
+	/// Pushes an array of the same length.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		let len = self.len();
+		push_array(vm, len, self.into_iter());
+	}
+}
+
+impl<'a, T: ToSquirrel + Clone> ToSquirrel for &'a [T] {
+	/// Pushes an array holding a copy of each element.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		push_array(vm, self.len(), self.iter().cloned());
+	}
+}
+
+impl<T: ToSquirrel, const N: usize> ToSquirrel for [T; N] {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		push_array(vm, N, IntoIterator::into_iter(self));
+	}
+}
+
+impl<T: FromSquirrel> FromSquirrel for Vec<T> {
+	/// Reads an array, failing if any element cannot be converted.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<Vec<T>, SquirrelError> {
+		let mut items = Vec::with_capacity(slot_count(vm, idx));
+		try!(read_array(vm, idx, |vm, _| {
+			items.push(try!(T::read(vm, -1)));
+			Ok(())
+		}));
+		Ok(items)
+	}
+}
+
+impl<T: FromSquirrel, const N: usize> FromSquirrel for [T; N] {
+	/// Reads an array, which must have exactly `N` elements.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<[T; N], SquirrelError> {
+		let items: Vec<T> = try!(vm.read(idx));
+		exact(items, idx)
+	}
+}
+
+/// Turns the elements read from the array at `idx` into a fixed size array.
+fn exact<T, const N: usize>(items: Vec<T>, idx: isize) -> Result<[T; N], SquirrelError> {
+	let len = items.len();
+	<[T; N]>::try_from(items).map_err(|_| {
+		SquirrelError::Runtime(format!("expected an array of length {} at index {}, found length {}", N, idx, len))
+	})
+}
+
+impl<K, V, S> ToSquirrel for HashMap<K, V, S> where K: ToSquirrel + Eq + Hash, V: ToSquirrel, S: BuildHasher {
+	/// Pushes a table with the same keys and values.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		let len = self.len();
+		push_table(vm, len, self.into_iter());
+	}
+}
+
+impl<K, V, S> FromSquirrel for HashMap<K, V, S> where K: FromSquirrel + Eq + Hash, V: FromSquirrel, S: BuildHasher + Default {
+	/// Reads a table, failing if any key or value cannot be converted.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<HashMap<K, V, S>, SquirrelError> {
+		let mut map = HashMap::with_capacity_and_hasher(slot_count(vm, idx), S::default());
+		try!(read_table(vm, idx, |vm| {
+			map.insert(try!(K::read(vm, -2)), try!(V::read(vm, -1)));
+			Ok(())
+		}));
+		Ok(map)
+	}
+}
+
+impl<K: ToSquirrel + Ord, V: ToSquirrel> ToSquirrel for BTreeMap<K, V> {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		let len = self.len();
+		push_table(vm, len, self.into_iter());
+	}
+}
+
+impl<K: FromSquirrel + Ord, V: FromSquirrel> FromSquirrel for BTreeMap<K, V> {
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<BTreeMap<K, V>, SquirrelError> {
+		let mut map = BTreeMap::new();
+		try!(read_table(vm, idx, |vm| {
+			map.insert(try!(K::read(vm, -2)), try!(V::read(vm, -1)));
+			Ok(())
+		}));
+		Ok(map)
+	}
+}
+
+impl<T, S> ToSquirrel for HashSet<T, S> where T: ToSquirrel + Eq + Hash, S: BuildHasher {
+	/// Pushes a table with each element as a key whose value is `true`, so that scripts can test membership with `in`.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		let len = self.len();
+		push_table(vm, len, self.into_iter().map(|item| (item, true)));
+	}
+}
+
+impl<T, S> FromSquirrel for HashSet<T, S> where T: FromSquirrel + Eq + Hash, S: BuildHasher + Default {
+	/// Reads the keys of a table, or the elements of an array.
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<HashSet<T, S>, SquirrelError> {
+		let mut set = HashSet::with_capacity_and_hasher(slot_count(vm, idx), S::default());
+		if unsafe { ffi::sq_gettype(vm.0, idx) } == ffi::SQObjectType::OT_ARRAY {
+			try!(read_array(vm, idx, |vm, _| {
+				set.insert(try!(T::read(vm, -1)));
+				Ok(())
+			}));
+		}
+		else {
+			try!(read_table(vm, idx, |vm| {
+				set.insert(try!(T::read(vm, -2)));
+				Ok(())
+			}));
+		}
+		Ok(set)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{exact, narrow};
+
+	#[test]
+	fn narrow_in_range() {
+		assert_eq!(narrow::<u8>(255, 2, "u8").unwrap(), 255);
+		assert_eq!(narrow::<i8>(-128, 2, "i8").unwrap(), -128);
+		assert_eq!(narrow::<usize>(0, 2, "usize").unwrap(), 0);
+	}
+
+	#[test]
+	fn narrow_out_of_range() {
+		let e = narrow::<u8>(300, 2, "u8").unwrap_err();
+		assert_eq!(e.to_string(), "integer 300 at index 2 is out of range for u8");
+		assert!(narrow::<usize>(-1, 3, "usize").is_err());
+		assert!(narrow::<i8>(128, 1, "i8").is_err());
+	}
+
+	#[test]
+	fn exact_length() {
+		let items: [i32; 3] = exact(vec![1, 2, 3], 2).unwrap();
+		assert_eq!(items, [1, 2, 3]);
+		let empty: [i32; 0] = exact(Vec::new(), 2).unwrap();
+		assert_eq!(empty, []);
+	}
+
+	#[test]
+	fn wrong_length() {
+		let e = exact::<i32, 2>(vec![1, 2, 3], -1).unwrap_err();
+		assert_eq!(e.to_string(), "expected an array of length 2 at index -1, found length 3");
+		assert!(exact::<i32, 4>(vec![1], 1).is_err());
+	}
+}
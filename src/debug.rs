@@ -0,0 +1,198 @@
+//! Runtime error reporting and call stack inspection.
+
+use ffi;
+use std::io::Write;
+use std::ptr;
+
+use {SquirrelVM, Value, InspectOptions};
+
+/// A single frame of a virtual machine's call stack.
+#[derive(Debug, Clone)]
+pub struct Frame {
+	/// The name of the function, or an empty string if it has none.
+	pub func_name: String,
+	/// The source name of the script containing the function.
+	pub source: String,
+	/// The line currently executing, or `-1` for native functions.
+	pub line: isize,
+	/// An opaque identifier for the function's prototype, or `None` for native functions.
+	pub func_id: Option<usize>,
+	/// The local variables of the frame, in declaration order.
+	pub locals: Vec<(String, Value)>
+}
+
+/// Describes a runtime error thrown by a script, as passed to a runtime error handler.
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+	/// The value that was thrown.
+	pub value: Value,
+	/// The value that was thrown as rendered by `SquirrelVM::inspect`, showing the contents of tables and instances.
+	pub rendered: String,
+	/// The source name of the script the error was thrown in.
+	pub source: String,
+	/// The line the error was thrown on.
+	pub line: isize,
+	/// The call stack at the point the error was thrown, innermost frame first.
+	pub backtrace: Vec<Frame>
+}
+
+/// Describes the error passed to the runtime error handler that is running.
+///
+/// The handler is called with the root table and the thrown value.
+pub fn capture_error<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>) -> RuntimeError {
+	let value = vm.read(2).unwrap_or(Value::Null);
+	let rendered = vm.inspect(2, InspectOptions::default());
+	// Level 0 is the handler itself
+	let backtrace = capture_backtrace(vm, 1);
+	let (source, line) = match backtrace.first() {
+		Some(frame) => (frame.source.clone(), frame.line),
+		None => (String::new(), -1)
+	};
+
+	RuntimeError {
+		value: value,
+		rendered: rendered,
+		source: source,
+		line: line,
+		backtrace: backtrace
+	}
+}
+
+/// Captures the call stack starting at `level`, leaving the stack unchanged.
+pub fn capture_backtrace<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, level: isize) -> Vec<Frame> {
+	let mut frames = Vec::new();
+	let mut level = level;
+
+	while let Ok(info) = vm.stack_info(level) {
+		let mut locals = Vec::new();
+		while let Some(local) = vm.get_local(level as usize, locals.len()) {
+			locals.push(local);
+		}
+
+		// Native functions have no line information or function prototype
+		let func_id = if info.line < 0 {
+			None
+		}
+		else {
+			let mut fi = ffi::SQFunctionInfo {
+				funcid: ptr::null_mut(),
+				name: ptr::null(),
+				source: ptr::null()
+			};
+			if ffi::SQ_SUCCEEDED(unsafe { ffi::sq_getfunctioninfo(vm.0, level, &mut fi) }) {
+				Some(fi.funcid as usize)
+			}
+			else {
+				None
+			}
+		};
+
+		frames.push(Frame {
+			func_name: info.func_name,
+			source: info.source,
+			line: info.line,
+			func_id: func_id,
+			locals: locals
+		});
+		level += 1;
+	}
+
+	frames
+}
+
+/// Renders a call stack in the same format as `sqstd_printcallstack`.
+///
+/// As with the standard library, only the locals of the first ten frames are included.
+pub fn render_call_stack(frames: &[Frame]) -> String {
+	let mut out = String::from("\nCALLSTACK\n");
+	for frame in frames {
+		let func_name = if frame.func_name.is_empty() { "unknown" } else { &frame.func_name[..] };
+		let source = if frame.source.is_empty() { "unknown" } else { &frame.source[..] };
+		out.push_str(&format!("*FUNCTION [{}()] {} line [{}]\n", func_name, source, frame.line));
+	}
+
+	out.push_str("\nLOCALS\n");
+	for frame in frames.iter().take(10) {
+		for (name, value) in &frame.locals {
+			let value = match *value {
+				Value::Null => "NULL".to_string(),
+				Value::Integer(i) => i.to_string(),
+				Value::Float(f) => format_float(f as f64, 14),
+				Value::Bool(b) => b.to_string(),
+				Value::String(ref s) => format!("\"{}\"", s),
+				Value::Closure(_) => "CLOSURE".to_string(),
+				Value::NativeClosure(_) => "NATIVECLOSURE".to_string(),
+				ref other => other.type_name().to_uppercase()
+			};
+			out.push_str(&format!("[{}] {}\n", name, value));
+		}
+	}
+	out
+}
+
+/// Formats a float like C's `%.*g`, with `precision` significant digits.
+///
+/// Squirrel's `tostring()` uses `%g`, which has a precision of 6, and its call stacks use `%.14g`.
+pub fn format_float(f: f64, precision: i32) -> String {
+	if f.is_nan() {
+		return "nan".to_string();
+	}
+	if f.is_infinite() {
+		return if f < 0.0 { "-inf" } else { "inf" }.to_string();
+	}
+	let precision = precision.max(1);
+
+	// Round to the precision first, as that may change the exponent
+	let exp_form = format!("{:.*e}", (precision - 1) as usize, f);
+	let exp: i32 = exp_form[exp_form.find('e').unwrap() + 1..].parse().unwrap();
+
+	if !(-4..precision).contains(&exp) {
+		let mantissa = trim_zeros(&exp_form[..exp_form.find('e').unwrap()]);
+		let sign = if exp < 0 { '-' } else { '+' };
+		format!("{}e{}{:02}", mantissa, sign, exp.abs())
+	}
+	else {
+		trim_zeros(&format!("{:.*}", (precision - 1 - exp) as usize, f)).to_string()
+	}
+}
+
+/// Removes trailing zeros after a decimal point, as `%g` does.
+fn trim_zeros(s: &str) -> &str {
+	if s.contains('.') {
+		s.trim_end_matches('0').trim_end_matches('.')
+	}
+	else {
+		s
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::f64::consts::PI;
+
+	use super::format_float;
+
+	#[test]
+	fn like_printf() {
+		assert_eq!(format_float(1.0, 6), "1");
+		assert_eq!(format_float(0.5, 6), "0.5");
+		assert_eq!(format_float(-2.25, 6), "-2.25");
+		assert_eq!(format_float(PI, 6), "3.14159");
+		assert_eq!(format_float(PI, 14), "3.1415926535898");
+		assert_eq!(format_float(100000.0, 6), "100000");
+		assert_eq!(format_float(1000000.0, 6), "1e+06");
+		assert_eq!(format_float(999999.5, 6), "1e+06");
+		assert_eq!(format_float(0.0001, 6), "0.0001");
+		assert_eq!(format_float(0.00001234, 6), "1.234e-05");
+		assert_eq!(format_float(1.5e300, 6), "1.5e+300");
+		assert_eq!(format_float(0.0, 6), "0");
+		assert_eq!(format_float(1.0, 0), "1");
+	}
+
+	#[test]
+	fn non_finite() {
+		assert_eq!(format_float(f64::INFINITY, 6), "inf");
+		assert_eq!(format_float(f64::NEG_INFINITY, 6), "-inf");
+		assert_eq!(format_float(f64::NAN, 6), "nan");
+	}
+}
@@ -0,0 +1,377 @@
+//! Delegates whose metamethods are implemented in Rust.
+
+use ffi;
+use std::cmp::Ordering;
+use std::io::Write;
+use std::rc::Rc;
+
+use convert::type_name;
+use {SquirrelVM, SquirrelError, NativeReturn, Thrown, Value};
+
+/// A pusher for one slot of a delegate.
+type Slot<P, E> = Box<dyn FnOnce(&mut SquirrelVM<P, E>)>;
+
+/// The metamethods Squirrel looks up in delegates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetaMethod {
+	/// `_get`, called when a key is missing: `(this, key)`.
+	Get,
+	/// `_set`, called when assigning to a missing key: `(this, key, value)`.
+	Set,
+	/// `_newslot`, called when `<-` creates a slot: `(this, key, value)`.
+	NewSlot,
+	/// `_delslot`, called by `delete`: `(this, key)`.
+	DelSlot,
+	/// `_add`, called by `+`: `(this, other)`.
+	Add,
+	/// `_sub`, called by `-`: `(this, other)`.
+	Sub,
+	/// `_mul`, called by `*`: `(this, other)`.
+	Mul,
+	/// `_div`, called by `/`: `(this, other)`.
+	Div,
+	/// `_modulo`, called by `%`: `(this, other)`.
+	Modulo,
+	/// `_unm`, called by unary `-`: `(this)`.
+	Unm,
+	/// `_typeof`, called by `typeof`: `(this)`.
+	TypeOf,
+	/// `_call`, called when the object is called: `(this, env, args...)`.
+	Call,
+	/// `_cloned`, called on the copy made by `clone`: `(this, original)`.
+	Cloned,
+	/// `_nexti`, called by `foreach` on userdata and instances: `(this, previous index)`.
+	NextI,
+	/// `_cmp`, called by comparisons, which must return an integer: `(this, other)`.
+	Cmp,
+	/// `_tostring`, called by `tostring()`, which must return a string: `(this)`.
+	ToString
+}
+
+impl MetaMethod {
+	/// Gets the name of the slot the metamethod is stored in.
+	pub fn name(&self) -> &'static str {
+		match *self {
+			MetaMethod::Get => "_get",
+			MetaMethod::Set => "_set",
+			MetaMethod::NewSlot => "_newslot",
+			MetaMethod::DelSlot => "_delslot",
+			MetaMethod::Add => "_add",
+			MetaMethod::Sub => "_sub",
+			MetaMethod::Mul => "_mul",
+			MetaMethod::Div => "_div",
+			MetaMethod::Modulo => "_modulo",
+			MetaMethod::Unm => "_unm",
+			MetaMethod::TypeOf => "_typeof",
+			MetaMethod::Call => "_call",
+			MetaMethod::Cloned => "_cloned",
+			MetaMethod::NextI => "_nexti",
+			MetaMethod::Cmp => "_cmp",
+			MetaMethod::ToString => "_tostring"
+		}
+	}
+}
+
+/// The behaviour of a Rust type when it is used from scripts through a delegate.
+///
+/// Every method has a default, so only the ones that are needed have to be implemented. `meta_methods` installs
+/// every metamethod, so the defaults do not behave as if it were missing: the operators and `compare` fail,
+/// `foreach` visits nothing, and the rest fall back to what Squirrel would do. Methods take `&self` because scripts can hold many references to the same object,
+/// so state that changes needs interior mutability, such as a `RefCell`.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// # use std::cell::Cell;
+/// struct Counter(Cell<isize>);
+///
+/// impl MetaMethods for Counter {
+/// 	fn get(&self, key: &Value) -> Option<Value> {
+/// 		match *key {
+/// 			Value::String(ref s) if s == "count" => Some(Value::Integer(self.0.get())),
+/// 			_ => None
+/// 		}
+/// 	}
+///
+/// 	fn call(&self, _: &[Value]) -> Result<Value, String> {
+/// 		self.0.set(self.0.get() + 1);
+/// 		Ok(Value::Null)
+/// 	}
+/// }
+///
+/// Delegate::new().meta_methods(Counter(Cell::new(0))).push_userdata(&mut vm);
+/// ```
+pub trait MetaMethods: 'static {
+	/// Reads a key that is missing from the object, returning `None` if it does not exist either.
+	fn get(&self, key: &Value) -> Option<Value> {
+		let _ = key;
+		None
+	}
+
+	/// Assigns to a key that is missing from the object, returning `false` if it cannot be set.
+	fn set(&self, key: &Value, value: &Value) -> bool {
+		let _ = (key, value);
+		false
+	}
+
+	/// Handles calling the object with the arguments, not including `this`.
+	fn call(&self, args: &[Value]) -> Result<Value, String> {
+		let _ = args;
+		Err("the object cannot be called".to_string())
+	}
+
+	/// Handles `this + other`.
+	fn add(&self, other: &Value) -> Result<Value, String> {
+		unsupported("+", other)
+	}
+
+	/// Handles `this - other`.
+	fn sub(&self, other: &Value) -> Result<Value, String> {
+		unsupported("-", other)
+	}
+
+	/// Handles `this * other`.
+	fn mul(&self, other: &Value) -> Result<Value, String> {
+		unsupported("*", other)
+	}
+
+	/// Handles `this / other`.
+	fn div(&self, other: &Value) -> Result<Value, String> {
+		unsupported("/", other)
+	}
+
+	/// Handles `this % other`.
+	fn modulo(&self, other: &Value) -> Result<Value, String> {
+		unsupported("%", other)
+	}
+
+	/// Handles `-this`.
+	fn unm(&self) -> Result<Value, String> {
+		Err("the unary - operator is not supported".to_string())
+	}
+
+	/// Compares the object with another value, for `<`, `>`, `<=`, `>=` and `<=>`.
+	///
+	/// By default every comparison fails, including with the object itself.
+	fn compare(&self, other: &Value) -> Result<Ordering, String> {
+		Err(format!("cannot compare with {}", other.type_name()))
+	}
+
+	/// Gets the text returned by `tostring()`, or `None` for the default.
+	fn tostring(&self) -> Option<String> {
+		None
+	}
+
+	/// Gets the index `foreach` visits after `prev`, which is `null` at the start, or `None` to stop.
+	///
+	/// The value at each index is read with `get`. Squirrel only calls this for userdata and instances,
+	/// as tables are always iterated directly. By default the object has no indexes, so `foreach` visits nothing.
+	fn next_index(&self, prev: &Value) -> Option<Value> {
+		let _ = prev;
+		None
+	}
+
+	/// Gets the name returned by `typeof`, or `None` for the name of the real type.
+	fn type_name(&self) -> Option<String> {
+		None
+	}
+}
+
+fn unsupported(op: &str, other: &Value) -> Result<Value, String> {
+	Err(format!("the {} operator is not supported with {}", op, other.type_name()))
+}
+
+/// Builds a delegate table, whose functions and metamethods are native closures.
+///
+/// Objects look up missing keys in their delegate, which is how tables and userdata get methods and
+/// metamethods. Each closure receives `this` at index 1, followed by the arguments listed on `MetaMethod`.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// vm.new_table();
+/// Delegate::new()
+/// 	.meta(MetaMethod::ToString, |vm| "a point")
+/// 	.function("length", |vm| -> Result<f64, SquirrelError> {
+/// 		let x: f64 = try!(vm.read(2));
+/// 		Ok(x.abs())
+/// 	})
+/// 	.attach(&mut vm, -1)
+/// 	.unwrap();
+/// ```
+pub struct Delegate<P, E> {
+	slots: Vec<(String, Slot<P, E>)>
+}
+
+impl<P: Write + Sync, E: Write + Sync> Delegate<P, E> {
+	/// Creates an empty delegate.
+	pub fn new() -> Delegate<P, E> {
+		Delegate {
+			slots: Vec::new()
+		}
+	}
+
+	/// Adds a function that objects using the delegate can call as a method.
+	pub fn function<F, R>(mut self, name: &str, func: F) -> Delegate<P, E>
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.slots.push((name.to_string(), Box::new(move |vm: &mut SquirrelVM<P, E>| vm.new_closure(func, 0))));
+		self
+	}
+
+	/// Adds a metamethod, replacing any earlier one of the same kind.
+	pub fn meta<F, R>(mut self, method: MetaMethod, func: F) -> Delegate<P, E>
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.slots.retain(|(name, _)| name != method.name());
+		self.function(method.name(), func)
+	}
+
+	/// Adds every metamethod of `MetaMethods`, calling into `value`.
+	///
+	/// The value is shared by every object the delegate is attached to.
+	pub fn meta_methods<T: MetaMethods>(self, value: T) -> Delegate<P, E> {
+		let value = Rc::new(value);
+		let v = value.clone();
+		let d = self.meta(MetaMethod::Get, move |vm| -> Result<Value, Thrown<Value>> {
+			// Throwing null tells Squirrel the key does not exist
+			v.get(&vm.type_of(2)).ok_or(Thrown(Value::Null))
+		});
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Set, move |vm| -> Result<(), Thrown<Value>> {
+			if v.set(&vm.type_of(2), &vm.type_of(3)) { Ok(()) } else { Err(Thrown(Value::Null)) }
+		});
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Call, move |vm| {
+			// Skip `this` and the environment the object was called with
+			let args: Vec<Value> = (3..vm.get_top() + 1).map(|i| vm.type_of(i)).collect();
+			v.call(&args)
+		});
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Add, move |vm| v.add(&vm.type_of(2)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Sub, move |vm| v.sub(&vm.type_of(2)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Mul, move |vm| v.mul(&vm.type_of(2)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Div, move |vm| v.div(&vm.type_of(2)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Modulo, move |vm| v.modulo(&vm.type_of(2)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Unm, move |_| v.unm());
+		let v = value.clone();
+		let d = d.meta(MetaMethod::Cmp, move |vm| v.compare(&vm.type_of(2)).map(|ordering| ordering as isize));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::ToString, move |vm| v.tostring().unwrap_or_else(|| default_tostring(vm)));
+		let v = value.clone();
+		let d = d.meta(MetaMethod::NextI, move |vm| v.next_index(&vm.type_of(2)).unwrap_or(Value::Null));
+		let v = value;
+		d.meta(MetaMethod::TypeOf, move |vm| {
+			v.type_name().unwrap_or_else(|| type_name(unsafe { ffi::sq_gettype(vm.0, 1) }).to_string())
+		})
+	}
+
+	/// Pushes the delegate table.
+	pub fn push(self, vm: &mut SquirrelVM<P, E>) {
+		vm.new_table();
+		for (name, push_value) in self.slots {
+			vm.push_value(&name[..]);
+			push_value(vm);
+			let _ = vm.raw_set(-3);
+		}
+	}
+
+	/// Makes the delegate the delegate of the table or userdata at `idx`.
+	pub fn attach(self, vm: &mut SquirrelVM<P, E>, idx: isize) -> Result<(), SquirrelError> {
+		let idx = if idx < 0 { vm.get_top() + idx + 1 } else { idx };
+		self.push(vm);
+		match vm.set_delegate(idx) {
+			Ok(()) => Ok(()),
+			Err(()) => {
+				vm.pop(1);
+				Err(SquirrelError::Runtime(vm.take_last_error()))
+			}
+		}
+	}
+
+	/// Pushes a new userdata with the delegate, whose behaviour is defined entirely by it.
+	pub fn push_userdata(self, vm: &mut SquirrelVM<P, E>) {
+		unsafe { ffi::sq_newuserdata(vm.0, 0); }
+		// A userdata can always have a delegate
+		let _ = self.attach(vm, -1);
+	}
+}
+
+impl<P: Write + Sync, E: Write + Sync> Default for Delegate<P, E> {
+	fn default() -> Delegate<P, E> {
+		Delegate::new()
+	}
+}
+
+/// Formats an object like Squirrel does when it has no `_tostring` metamethod.
+fn default_tostring<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>) -> String {
+	let t = unsafe { ffi::sq_gettype(vm.0, 1) };
+	let handle = vm.handle(1);
+	format!("({} : {:#x})", type_name(t), handle.raw()._unVal.raw)
+}
+
+#[cfg(test)]
+mod tests {
+	use ffi;
+	use std::cmp::Ordering;
+
+	use super::{MetaMethod, MetaMethods};
+	use Value;
+
+	struct Empty;
+
+	impl MetaMethods for Empty {}
+
+	struct Number(ffi::SQInteger);
+
+	impl MetaMethods for Number {
+		fn add(&self, other: &Value) -> Result<Value, String> {
+			match *other {
+				Value::Integer(i) => Ok(Value::Integer(self.0 + i)),
+				_ => Err("not a number".to_string())
+			}
+		}
+
+		fn compare(&self, other: &Value) -> Result<Ordering, String> {
+			Value::Integer(self.0).partial_cmp(other).ok_or_else(|| "not a number".to_string())
+		}
+	}
+
+	#[test]
+	fn names() {
+		assert_eq!(MetaMethod::Get.name(), "_get");
+		assert_eq!(MetaMethod::NextI.name(), "_nexti");
+		assert_eq!(MetaMethod::Cmp.name(), "_cmp");
+		assert_eq!(MetaMethod::ToString.name(), "_tostring");
+	}
+
+	#[test]
+	fn defaults() {
+		let key = Value::String("key".to_string());
+		assert_eq!(Empty.get(&key), None);
+		assert!(!Empty.set(&key, &Value::Null));
+		assert_eq!(Empty.call(&[]), Err("the object cannot be called".to_string()));
+		assert_eq!(Empty.add(&Value::Integer(1)), Err("the + operator is not supported with integer".to_string()));
+		assert_eq!(Empty.unm(), Err("the unary - operator is not supported".to_string()));
+		assert_eq!(Empty.compare(&Value::Null), Err("cannot compare with null".to_string()));
+		assert_eq!(Empty.next_index(&Value::Null), None);
+		assert_eq!(Empty.tostring(), None);
+		assert_eq!(Empty.type_name(), None);
+	}
+
+	#[test]
+	fn overrides() {
+		let n = Number(2);
+		assert_eq!(n.add(&Value::Integer(3)), Ok(Value::Integer(5)));
+		assert_eq!(n.compare(&Value::Integer(3)), Ok(Ordering::Less));
+		assert_eq!(n.compare(&Value::Null), Err("not a number".to_string()));
+		assert_eq!(n.sub(&Value::Integer(3)), Err("the - operator is not supported with integer".to_string()));
+	}
+}
@@ -0,0 +1,207 @@
+//! Compiler diagnostics.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Represents a compiler error thrown by a SquirrelVM.
+#[derive(Debug, Clone)]
+pub struct CompilerError {
+	/// A description of the error.
+	pub desc: String,
+	/// The source name of the script that caused the error.
+	pub source: String,
+	/// The line the error is on.
+	pub line: isize,
+	/// The column the error is on.
+	pub column: isize,
+	/// The text of the line the error is on, if the source code is known.
+	pub line_text: Option<String>
+}
+
+impl CompilerError {
+	/// Renders the error along with the offending line of source code, with a caret marking the column.
+	///
+	/// # Example output
+	/// ```text
+	/// error: expression expected
+	///  --> script.nut:2:11
+	///   |
+	/// 2 | local x = ;
+	///   |           ^
+	/// ```
+	pub fn render(&self) -> String {
+		let mut out = format!("error: {}\n", self.desc);
+		let gutter = self.line.to_string().len();
+		let pad = " ".repeat(gutter);
+		out.push_str(&format!("{} --> {}:{}:{}\n", pad, self.source, self.line, self.column));
+
+		if let Some(ref text) = self.line_text {
+			// Keep tabs so the caret lines up however they are displayed
+			let caret: String = text.chars()
+				.take((self.column - 1).max(0) as usize)
+				.map(|c| if c == '\t' { '\t' } else { ' ' })
+				.collect();
+			out.push_str(&format!("{} |\n", pad));
+			out.push_str(&format!("{} | {}\n", self.line, text));
+			out.push_str(&format!("{} | {}^\n", pad, caret));
+		}
+		out
+	}
+}
+
+impl fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Error: {} in '{}' on line '{}', column {}", self.desc, self.source, self.line, self.column)
+	}
+}
+
+impl Error for CompilerError {
+    fn description(&self) -> &str {
+		&self.desc[..]
+	}
+}
+
+/// Attaches the offending line of `src` to an error.
+pub fn attach_source(error: &mut CompilerError, src: &str) {
+	if error.line >= 1 {
+		error.line_text = src.lines().nth((error.line - 1) as usize).map(|l| l.trim_end_matches('\r').to_string());
+	}
+}
+
+/// Checks that neither the source code nor the name of a script contains a null character.
+///
+/// The compiler takes a null character as the end of the script, so it would silently ignore the rest.
+pub fn check_nulls(src: &str, name: &str) -> Result<(), CompilerError> {
+	if name.contains('\0') {
+		return Err(CompilerError {
+			desc: "the script name contains a null character".to_string(),
+			source: name.replace('\0', "\\0"),
+			line: 0,
+			column: 0,
+			line_text: None
+		});
+	}
+	for (i, line) in src.split('\n').enumerate() {
+		if let Some(pos) = line.find('\0') {
+			let mut error = CompilerError {
+				desc: "null characters are not allowed in scripts".to_string(),
+				source: name.to_string(),
+				line: i as isize + 1,
+				column: line[..pos].chars().count() as isize + 1,
+				line_text: None
+			};
+			attach_source(&mut error, src);
+			return Err(error);
+		}
+	}
+	Ok(())
+}
+
+/// Blanks out a line of `src` so that compilation can resume after an error on it.
+///
+/// Line numbering is preserved so later errors refer to the original source.
+pub fn blank_line(src: &str, line: isize) -> String {
+	src.split('\n')
+		.enumerate()
+		.map(|(i, l)| if i as isize == line - 1 { "" } else { l })
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Reads the source code of a script file, decoding it the same way the standard library does.
+///
+/// Returns `None` for compiled bytecode or if the file cannot be read.
+pub fn read_source(path: &Path) -> Option<String> {
+	let mut bytes = Vec::new();
+	if File::open(path).and_then(|mut f| f.read_to_end(&mut bytes)).is_err() {
+		return None;
+	}
+	decode_source(&bytes)
+}
+
+/// Decodes the contents of a script file, which may be UTF-8 (with or without a byte order mark) or UTF-16.
+///
+/// Returns `None` for compiled bytecode.
+pub fn decode_source(bytes: &[u8]) -> Option<String> {
+	let utf16 = |b: &[u8], le: bool| -> String {
+		let units: Vec<u16> = b.chunks(2)
+			.filter(|c| c.len() == 2)
+			.map(|c| if le { (c[1] as u16) << 8 | c[0] as u16 } else { (c[0] as u16) << 8 | c[1] as u16 })
+			.collect();
+		String::from_utf16_lossy(&units)
+	};
+
+	match bytes.get(0..2) {
+		Some([0xFA, 0xFA]) => None,
+		Some([0xFF, 0xFE]) => Some(utf16(&bytes[2..], true)),
+		Some([0xFE, 0xFF]) => Some(utf16(&bytes[2..], false)),
+		_ if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) => Some(String::from_utf8_lossy(&bytes[3..]).into_owned()),
+		_ => Some(String::from_utf8_lossy(bytes).into_owned())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CompilerError, attach_source, blank_line, check_nulls, decode_source};
+
+	#[test]
+	fn blank_line_keeps_numbering() {
+		assert_eq!(blank_line("a\nb\nc", 2), "a\n\nc");
+		assert_eq!(blank_line("a\nb\nc", 4), "a\nb\nc");
+		assert_eq!(blank_line("a\r\nb", 1), "\nb");
+	}
+
+	#[test]
+	fn nulls_in_source() {
+		let e = check_nulls("local a = 1;\nlocal b\0 = 2;", "test.nut").unwrap_err();
+		assert_eq!(e.source, "test.nut");
+		assert_eq!((e.line, e.column), (2, 8));
+		assert_eq!(e.line_text, Some("local b\0 = 2;".to_string()));
+		assert!(check_nulls("local a = 1;", "test.nut").is_ok());
+	}
+
+	#[test]
+	fn nulls_in_name() {
+		let e = check_nulls("", "te\0st").unwrap_err();
+		assert_eq!(e.source, "te\\0st");
+		assert_eq!(e.line, 0);
+	}
+
+	#[test]
+	fn render_marks_column() {
+		let mut e = CompilerError {
+			desc: "expression expected".to_string(),
+			source: "script.nut".to_string(),
+			line: 2,
+			column: 11,
+			line_text: None
+		};
+		attach_source(&mut e, "local a;\r\nlocal x = ;\r\n");
+		assert_eq!(e.render(), "error: expression expected\n  --> script.nut:2:11\n  |\n2 | local x = ;\n  |           ^\n");
+	}
+
+	#[test]
+	fn render_keeps_tabs() {
+		let e = CompilerError {
+			desc: "expression expected".to_string(),
+			source: "script.nut".to_string(),
+			line: 1,
+			column: 3,
+			line_text: Some("\tx;".to_string())
+		};
+		assert!(e.render().ends_with("1 | \tx;\n  | \t ^\n"));
+	}
+
+	#[test]
+	fn decode_encodings() {
+		assert_eq!(decode_source(b"print(1)"), Some("print(1)".to_string()));
+		assert_eq!(decode_source(b"\xEF\xBB\xBFprint(1)"), Some("print(1)".to_string()));
+		assert_eq!(decode_source(b"\xFF\xFEp\x00r\x00"), Some("pr".to_string()));
+		assert_eq!(decode_source(b"\xFE\xFF\x00p\x00r"), Some("pr".to_string()));
+		assert_eq!(decode_source(b"\xFA\xFA\x01\x02"), None);
+		assert_eq!(decode_source(b""), Some(String::new()));
+	}
+}
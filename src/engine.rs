@@ -0,0 +1,145 @@
+//! Configuring a virtual machine in one place.
+
+use std::io::{self, Write, Stdout, Stderr};
+
+use {SquirrelVM, SquirrelError, SquirrelModule, ModuleResolver, RuntimeError, StdLibs, Limits};
+
+/// A step run by `build` once the virtual machine has been created.
+type Setup<P, E> = Box<dyn FnOnce(&mut SquirrelVM<P, E>) -> Result<(), SquirrelError>>;
+
+/// The closure given to `on_runtime_error`.
+type ErrorHandler = Box<dyn Fn(&RuntimeError)>;
+
+/// Builds a virtual machine with its standard library, error handling and modules set up.
+///
+/// By default, every standard library module is registered, the standard library's error handler is used,
+/// and debug info is disabled.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::Write;
+/// # use std::time::Duration;
+/// # struct Greeter;
+/// # impl<P: Write + Sync, E: Write + Sync> SquirrelModule<P, E> for Greeter {
+/// # 	fn name(&self) -> &str { "greeter" }
+/// # 	fn version(&self) -> &str { "1.0.0" }
+/// # 	fn register(&self, _: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError> { Ok(()) }
+/// # }
+/// let mut vm = EngineBuilder::stdio()
+/// 	.stack_size(2048)
+/// 	.stdlib(StdLibs::sandboxed())
+/// 	.debug_info(true)
+/// 	.limits(Limits::none().timeout(Duration::from_secs(5)))
+/// 	.module_resolver(FsResolver::new().path("scripts"))
+/// 	.install(Greeter)
+/// 	.build()
+/// 	.unwrap();
+/// ```
+pub struct EngineBuilder<P, E> {
+	stack_size: isize,
+	print: P,
+	error: E,
+	stdlib: StdLibs,
+	debug_info: bool,
+	notify_all_exceptions: bool,
+	error_handler: Option<ErrorHandler>,
+	limits: Limits,
+	setup: Vec<Setup<P, E>>
+}
+
+impl EngineBuilder<Stdout, Stderr> {
+	/// Creates a builder for a virtual machine that prints to standard output and standard error.
+	pub fn stdio() -> EngineBuilder<Stdout, Stderr> {
+		EngineBuilder::new(io::stdout(), io::stderr())
+	}
+}
+
+impl<P: Write + Sync, E: Write + Sync> EngineBuilder<P, E> {
+	/// Creates a builder for a virtual machine that prints to `print` and writes errors to `error`.
+	pub fn new(print: P, error: E) -> EngineBuilder<P, E> {
+		EngineBuilder {
+			stack_size: 1024,
+			print: print,
+			error: error,
+			stdlib: StdLibs::all(),
+			debug_info: false,
+			notify_all_exceptions: false,
+			error_handler: None,
+			limits: Limits::none(),
+			setup: Vec::new()
+		}
+	}
+
+	/// Sets the initial stack size.
+	pub fn stack_size(mut self, size: isize) -> EngineBuilder<P, E> {
+		self.stack_size = size;
+		self
+	}
+
+	/// Selects the standard library modules to register.
+	pub fn stdlib(mut self, libs: StdLibs) -> EngineBuilder<P, E> {
+		self.stdlib = libs;
+		self
+	}
+
+	/// Enables or disables debug info for scripts compiled by the virtual machine.
+	pub fn debug_info(mut self, enable: bool) -> EngineBuilder<P, E> {
+		self.debug_info = enable;
+		self
+	}
+
+	/// Enables or disables calling the error handler for exceptions that are caught by scripts.
+	pub fn notify_all_exceptions(mut self, enable: bool) -> EngineBuilder<P, E> {
+		self.notify_all_exceptions = enable;
+		self
+	}
+
+	/// Handles runtime errors with `handler` instead of the standard library's error handler.
+	pub fn on_runtime_error<F: Fn(&RuntimeError) + 'static>(mut self, handler: F) -> EngineBuilder<P, E> {
+		self.error_handler = Some(Box::new(handler));
+		self
+	}
+
+	/// Sets the limits on how much work scripts may do, as with `SquirrelVM::set_limits`.
+	///
+	/// The time limit applies to each call into the machine, not to the time since it was built.
+	pub fn limits(mut self, limits: Limits) -> EngineBuilder<P, E> {
+		self.limits = limits;
+		self
+	}
+
+	/// Adds `require`, loading modules found by `resolver`.
+	pub fn module_resolver<R>(mut self, resolver: R) -> EngineBuilder<P, E>
+		where R: ModuleResolver<P, E> + 'static, P: 'static, E: 'static
+	{
+		self.setup.push(Box::new(move |vm: &mut SquirrelVM<P, E>| vm.set_module_resolver(resolver)));
+		self
+	}
+
+	/// Installs a module. Modules are installed in the order they are added, after the standard library.
+	pub fn install<M>(mut self, module: M) -> EngineBuilder<P, E>
+		where M: SquirrelModule<P, E> + 'static
+	{
+		self.setup.push(Box::new(move |vm: &mut SquirrelVM<P, E>| vm.install(module)));
+		self
+	}
+
+	/// Creates the virtual machine, returning an error if any library or module fails to load.
+	pub fn build(self) -> Result<SquirrelVM<P, E>, SquirrelError> {
+		let mut vm = SquirrelVM::new(self.stack_size, self.print, self.error);
+		vm.set_debug_info(self.debug_info);
+		vm.set_notify_all_exceptions(self.notify_all_exceptions);
+		try!(self.stdlib.register(&mut vm));
+
+		match self.error_handler {
+			Some(handler) => vm.on_runtime_error(handler),
+			None => vm.set_default_error_handler()
+		}
+		vm.set_limits(self.limits);
+
+		for step in self.setup {
+			try!(step(&mut vm));
+		}
+		Ok(vm)
+	}
+}
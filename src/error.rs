@@ -0,0 +1,72 @@
+//! Error types shared by the high level wrapper.
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+use CompilerError;
+
+/// Represents an error raised while working with a virtual machine.
+#[derive(Debug, Clone)]
+pub enum SquirrelError {
+	/// A stack slot did not hold a value of the expected type.
+	Type {
+		/// The stack index that was read.
+		idx: isize,
+		/// The name of the expected type.
+		expected: &'static str,
+		/// The name of the type that was found.
+		found: &'static str
+	},
+	/// A runtime error, either thrown by a script or by native code.
+	Runtime(String),
+	/// A script failed to compile.
+	Compile(CompilerError),
+	/// A file could not be read or written.
+	Io {
+		/// The path of the file.
+		path: PathBuf,
+		/// A description of the error.
+		desc: String
+	},
+	/// A format string could not be applied to its arguments.
+	Format {
+		/// The format string.
+		format: String,
+		/// A description of the error.
+		desc: String
+	},
+	/// A module with the same name has already been installed.
+	AlreadyInstalled {
+		/// The name of the module.
+		name: String,
+		/// The version of the module that is installed.
+		version: String
+	}
+}
+
+impl fmt::Display for SquirrelError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			SquirrelError::Type { idx, expected, found } => write!(f, "expected {} at index {}, found {}", expected, idx, found),
+			SquirrelError::Runtime(ref msg) => write!(f, "{}", msg),
+			SquirrelError::Compile(ref e) => write!(f, "{}", e),
+			SquirrelError::Io { ref path, ref desc } => write!(f, "{}: {}", path.display(), desc),
+			SquirrelError::Format { ref format, ref desc } => write!(f, "{} in format string '{}'", desc, format),
+			SquirrelError::AlreadyInstalled { ref name, ref version } => write!(f, "module '{}' is already installed (version {})", name, version)
+		}
+	}
+}
+
+impl Error for SquirrelError {
+	fn description(&self) -> &str {
+		match *self {
+			SquirrelError::Type { .. } => "type mismatch",
+			SquirrelError::Runtime(ref msg) => &msg[..],
+			SquirrelError::Compile(ref e) => &e.desc[..],
+			SquirrelError::Io { ref desc, .. } => &desc[..],
+			SquirrelError::Format { ref desc, .. } => &desc[..],
+			SquirrelError::AlreadyInstalled { .. } => "module already installed"
+		}
+	}
+}
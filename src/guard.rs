@@ -0,0 +1,68 @@
+//! Scoped stack management.
+
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+
+use SquirrelVM;
+
+/// Restores the top of a virtual machine's stack when dropped.
+///
+/// The guard dereferences to the virtual machine, so it can be used in its place.
+/// Values borrowed from the stack through the guard, such as blobs, cannot outlive it.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// # vm.push_root_table();
+/// # vm.push_value("x");
+/// # vm.push_value(1);
+/// # vm.new_slot(-3, false).unwrap();
+/// # vm.pop(1);
+/// {
+/// 	let mut guard = vm.guard();
+/// 	guard.push_root_table();
+/// 	guard.push_value("x");
+/// 	guard.get(-2).unwrap();
+/// } // the root table and the value of x are popped here
+/// ```
+pub struct StackGuard<'a, P: Write + Sync + 'a, E: Write + Sync + 'a> {
+	vm: &'a mut SquirrelVM<P, E>,
+	top: isize
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> StackGuard<'a, P, E> {
+	/// Creates a guard that restores the stack to its current top.
+	pub fn new(vm: &'a mut SquirrelVM<P, E>) -> StackGuard<'a, P, E> {
+		let top = vm.get_top();
+		StackGuard {
+			vm: vm,
+			top: top
+		}
+	}
+
+	/// Gets the top of the stack that will be restored.
+	pub fn saved_top(&self) -> isize {
+		self.top
+	}
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> Deref for StackGuard<'a, P, E> {
+	type Target = SquirrelVM<P, E>;
+
+	fn deref(&self) -> &SquirrelVM<P, E> {
+		self.vm
+	}
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> DerefMut for StackGuard<'a, P, E> {
+	fn deref_mut(&mut self) -> &mut SquirrelVM<P, E> {
+		self.vm
+	}
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> Drop for StackGuard<'a, P, E> {
+	fn drop(&mut self) {
+		self.vm.set_top(self.top);
+	}
+}
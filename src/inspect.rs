@@ -0,0 +1,292 @@
+//! Human-readable rendering of values, including the contents of tables and arrays.
+
+use ffi;
+use std::cmp::Ordering;
+use std::io::Write;
+
+use convert::type_name;
+use {SquirrelVM, Value, Handle};
+
+const INDENT: &'static str = "  ";
+
+/// Controls how `SquirrelVM::inspect` renders values.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// # vm.new_table();
+/// let text = vm.inspect(-1, InspectOptions::default().max_depth(2).max_width(10));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InspectOptions {
+	max_depth: usize,
+	max_width: usize,
+	sort_keys: bool
+}
+
+impl InspectOptions {
+	/// Sets how many levels of nested tables, arrays, instances and classes are expanded.
+	///
+	/// Deeper containers are shown as `{ ... }`. The default is 4.
+	pub fn max_depth(mut self, depth: usize) -> InspectOptions {
+		self.max_depth = depth;
+		self
+	}
+
+	/// Sets how many entries of each container are shown before the rest are summarized. The default is 100.
+	pub fn max_width(mut self, width: usize) -> InspectOptions {
+		self.max_width = width;
+		self
+	}
+
+	/// Sets whether the keys of tables, instances and classes are sorted, which makes the output stable.
+	///
+	/// Otherwise keys are shown in iteration order. The default is to sort.
+	pub fn sort_keys(mut self, sort: bool) -> InspectOptions {
+		self.sort_keys = sort;
+		self
+	}
+}
+
+impl Default for InspectOptions {
+	fn default() -> InspectOptions {
+		InspectOptions {
+			max_depth: 4,
+			max_width: 100,
+			sort_keys: true
+		}
+	}
+}
+
+/// A slot found while iterating a container.
+struct Entry {
+	key: Value,
+	value: Handle
+}
+
+/// Renders the value at `idx`, which must be an absolute index.
+pub fn inspect<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, idx: isize, options: &InspectOptions) -> String {
+	let mut ancestors = Vec::new();
+	render(vm, idx, options, 0, &mut ancestors)
+}
+
+fn render<P, E>(vm: &mut SquirrelVM<P, E>, idx: isize, options: &InspectOptions, depth: usize, ancestors: &mut Vec<u64>) -> String
+	where P: Write + Sync, E: Write + Sync
+{
+	use ffi::SQObjectType::*;
+
+	let t = unsafe { ffi::sq_gettype(vm.0, idx) };
+	match t {
+		OT_TABLE | OT_ARRAY | OT_INSTANCE | OT_CLASS => render_container(vm, idx, t, options, depth, ancestors),
+		OT_CLOSURE | OT_NATIVECLOSURE => {
+			let name = closure_name(vm, idx);
+			let kind = if t == OT_NATIVECLOSURE { "native function" } else { "function" };
+			match name {
+				Some(name) => format!("{} {}", kind, name),
+				None => kind.to_string()
+			}
+		},
+		OT_STRING => format!("{:?}", vm.read::<String>(idx).unwrap_or_default()),
+		OT_FLOAT => {
+			let s = vm.read::<Value>(idx).map(|v| v.to_string()).unwrap_or_default();
+			// Keep floats distinguishable from integers
+			if s.contains(|c: char| c == '.' || c == 'e' || c.is_alphabetic()) { s } else { s + ".0" }
+		},
+		_ => vm.read::<Value>(idx).map(|v| v.to_string()).unwrap_or_else(|_| format!("({})", type_name(t)))
+	}
+}
+
+fn render_container<P, E>(vm: &mut SquirrelVM<P, E>, idx: isize, t: ffi::SQObjectType, options: &InspectOptions, depth: usize, ancestors: &mut Vec<u64>) -> String
+	where P: Write + Sync, E: Write + Sync
+{
+	let (open, close) = match t {
+		ffi::SQObjectType::OT_ARRAY => ("[", "]"),
+		ffi::SQObjectType::OT_INSTANCE => ("instance {", "}"),
+		ffi::SQObjectType::OT_CLASS => ("class {", "}"),
+		_ => ("{", "}")
+	};
+	let id = identity(vm, idx);
+	if ancestors.contains(&id) {
+		return format!("{} <cycle> {}", open, close);
+	}
+	if depth >= options.max_depth {
+		return format!("{} ... {}", open, close);
+	}
+
+	// Methods belong to the class, so only show the fields of instances
+	let is_array = t == ffi::SQObjectType::OT_ARRAY;
+	let skip_functions = t == ffi::SQObjectType::OT_INSTANCE;
+	let mut entries = Vec::new();
+	each_slot(vm, idx, |vm| {
+		let value_type = unsafe { ffi::sq_gettype(vm.0, -1) };
+		if skip_functions && (value_type == ffi::SQObjectType::OT_CLOSURE || value_type == ffi::SQObjectType::OT_NATIVECLOSURE) {
+			return;
+		}
+		entries.push(Entry {
+			key: vm.read(-2).unwrap_or(Value::Null),
+			value: vm.handle(-1)
+		});
+	});
+
+	if options.sort_keys && !is_array {
+		entries.sort_by(|a, b| compare_keys(&a.key, &b.key));
+	}
+	let hidden = entries.len().saturating_sub(options.max_width);
+	entries.truncate(options.max_width);
+	if entries.is_empty() && hidden == 0 {
+		return format!("{}{}", open, close);
+	}
+
+	// Only the values that are shown are rendered, so hidden ones are never visited
+	let pad = INDENT.repeat(depth + 1);
+	ancestors.push(id);
+	let mut lines: Vec<String> = entries.iter().map(|entry| {
+		let value = {
+			let mut vm = vm.guard();
+			vm.push_handle(&entry.value);
+			let top = vm.get_top();
+			render(&mut vm, top, options, depth + 1, ancestors)
+		};
+		if is_array {
+			format!("{}{}", pad, value)
+		}
+		else {
+			format!("{}{} = {}", pad, render_key(&entry.key), value)
+		}
+	}).collect();
+	ancestors.pop();
+	if hidden > 0 {
+		lines.push(format!("{}... {} more", pad, hidden));
+	}
+	format!("{}\n{}\n{}{}", open, lines.join(",\n"), INDENT.repeat(depth), close)
+}
+
+/// Calls `f` with each key and value of the container at `idx` pushed.
+///
+/// Instances cannot be iterated without a `_nexti` metamethod, so their members are found through
+/// their class and each value is read from the instance.
+fn each_slot<P, E, F>(vm: &mut SquirrelVM<P, E>, idx: isize, mut f: F)
+	where P: Write + Sync, E: Write + Sync, F: FnMut(&mut SquirrelVM<P, E>)
+{
+	let mut vm = vm.guard();
+	if unsafe { ffi::sq_gettype(vm.0, idx) } != ffi::SQObjectType::OT_INSTANCE {
+		vm.push_null();
+		while vm.next(idx).is_ok() {
+			f(&mut vm);
+			vm.pop(2);
+		}
+		return;
+	}
+
+	if ffi::SQ_FAILED(unsafe { ffi::sq_getclass(vm.0, idx) }) {
+		vm.reset_error();
+		return;
+	}
+	let class = vm.get_top();
+	vm.push_null();
+	while vm.next(class).is_ok() {
+		// Replace the default value held by the class with the instance's own
+		vm.pop(1);
+		vm.push(-1);
+		if vm.raw_get(idx).is_ok() {
+			f(&mut vm);
+			vm.pop(2);
+		}
+		else {
+			vm.pop(1);
+		}
+	}
+	// Lookups that miss leave an error behind
+	vm.reset_error();
+}
+
+/// Gets an identifier for the object at `idx`, used to detect cycles.
+fn identity<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> u64 {
+	unsafe {
+		let mut obj = ffi::HSQOBJECT {
+			_type: ffi::SQObjectType::OT_NULL,
+			_unVal: ffi::SQObjectValue { raw: 0 }
+		};
+		ffi::sq_getstackobj(vm.0, idx, &mut obj);
+		obj._unVal.raw as u64
+	}
+}
+
+/// Gets the name of the closure at `idx`, if it has one.
+fn closure_name<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, idx: isize) -> Option<String> {
+	if ffi::SQ_FAILED(unsafe { ffi::sq_getclosurename(vm.0, idx) }) {
+		return None;
+	}
+	let name = vm.read::<String>(-1).ok();
+	vm.pop(1);
+	name
+}
+
+/// Orders integer keys numerically before other keys, which are ordered by their text.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+	match (a, b) {
+		(&Value::Integer(a), &Value::Integer(b)) => a.cmp(&b),
+		(&Value::Integer(_), _) => Ordering::Less,
+		(_, &Value::Integer(_)) => Ordering::Greater,
+		_ => a.to_string().cmp(&b.to_string())
+	}
+}
+
+/// Renders a key as it would be written in a table literal.
+fn render_key(key: &Value) -> String {
+	match *key {
+		Value::String(ref s) if is_identifier(s) => s.clone(),
+		Value::String(ref s) => format!("[{:?}]", s),
+		ref key => format!("[{}]", key)
+	}
+}
+
+fn is_identifier(s: &str) -> bool {
+	let mut chars = s.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+		_ => false
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{InspectOptions, compare_keys, render_key, is_identifier};
+	use std::cmp::Ordering;
+	use Value;
+
+	#[test]
+	fn identifiers() {
+		assert!(is_identifier("_name1"));
+		assert!(!is_identifier("1name"));
+		assert!(!is_identifier("a-b"));
+		assert!(!is_identifier(""));
+	}
+
+	#[test]
+	fn keys_as_literals() {
+		assert_eq!(render_key(&Value::String("x".to_string())), "x");
+		assert_eq!(render_key(&Value::String("two words".to_string())), "[\"two words\"]");
+		assert_eq!(render_key(&Value::Integer(3)), "[3]");
+	}
+
+	#[test]
+	fn key_order() {
+		let mut keys = [
+			Value::String("b".to_string()),
+			Value::Integer(10),
+			Value::String("a".to_string()),
+			Value::Integer(2)
+		];
+		keys.sort_by(compare_keys);
+		assert_eq!(keys.iter().map(render_key).collect::<Vec<_>>(), ["[2]", "[10]", "a", "b"]);
+		assert_eq!(compare_keys(&Value::Integer(1), &Value::Integer(1)), Ordering::Equal);
+	}
+
+	#[test]
+	fn options() {
+		let options = InspectOptions::default().max_depth(2).max_width(10).sort_keys(false);
+		assert_eq!((options.max_depth, options.max_width, options.sort_keys), (2, 10, false));
+	}
+}
@@ -7,11 +7,56 @@ use libc::c_char;
 use std::marker::PhantomData;
 use std::ffi::{CStr, CString};
 use std::{ptr, mem};
-use std::error::Error;
-use std::fmt;
-use std::io::Write;
+use std::io::{self, Read, Write};
 use std::str::from_utf8;
 use std::slice;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::fs::File;
+use std::path::Path;
+use std::rc::Rc;
+use std::cell::Cell;
+
+pub use array::ArrayRef;
+pub use class::{ClassBuilder, SquirrelClass};
+pub use convert::{ToSquirrel, FromSquirrel};
+pub use debug::{Frame, RuntimeError, render_call_stack};
+pub use delegate::{Delegate, MetaMethod, MetaMethods};
+pub use diagnostics::{CompilerError, decode_source};
+pub use engine::EngineBuilder;
+pub use error::SquirrelError;
+pub use guard::StackGuard;
+pub use inspect::InspectOptions;
+pub use limits::Limits;
+pub use module::{SquirrelModule, ModuleInfo};
+pub use stream::Stream;
+pub use native::{NativeReturn, ThrowError, Thrown, RawReturn};
+pub use require::{ModuleResolver, Module, NativeLoader, FsResolver, EmbeddedResolver, NativeResolver};
+pub use stdlib::StdLibs;
+pub use table::TableRef;
+pub use value::{Value, Handle};
+pub use vfs::{Vfs, MemoryVfs, JailVfs, OpenMode, Metadata};
+
+mod array;
+mod class;
+mod convert;
+mod debug;
+mod delegate;
+mod diagnostics;
+mod engine;
+mod error;
+mod guard;
+mod inspect;
+mod limits;
+mod module;
+mod native;
+pub mod rex;
+mod require;
+mod stream;
+mod stdlib;
+mod table;
+mod value;
+mod vfs;
 
 /// Print shim callback type
 type PrintFn = extern fn(v: ffi::HSQUIRRELVM, len: usize, buf: *const c_char);
@@ -25,56 +70,263 @@ extern {
 }
 
 /// Print callback
-extern fn print_fn<P: Write, E: Write>(v: ffi::HSQUIRRELVM, len: usize, buf: *const c_char) {
+extern fn print_fn(v: ffi::HSQUIRRELVM, len: usize, buf: *const c_char) {
 	let buffer = unsafe { slice::from_raw_parts(buf as *const u8, len) };
 	
-	let data: &mut SquirrelData<P, E> = unsafe { mem::transmute(ffi::sq_getforeignptr(v)) };
-	data.print.write(buffer).unwrap();
+	if let Some(data) = unsafe { vm_state(v) } {
+		let write = data.print;
+		write_stream(data, write, buffer);
+	}
 }
 
 /// Error callback
-extern fn err_fn<P: Write, E: Write>(v: ffi::HSQUIRRELVM, len: usize, buf: *const c_char) {
+extern fn err_fn(v: ffi::HSQUIRRELVM, len: usize, buf: *const c_char) {
 	let buffer = unsafe { slice::from_raw_parts(buf as *const u8, len) };
 	
-	let data: &mut SquirrelData<P, E> = unsafe { mem::transmute(ffi::sq_getforeignptr(v)) };
-	data.error.write(buffer).unwrap();
+	if let Some(data) = unsafe { vm_state(v) } {
+		let write = data.error;
+		write_stream(data, write, buffer);
+	}
+}
+
+/// Writes to a stream of the machine `data` belongs to, keeping any panic to re-raise later.
+fn write_stream(data: &mut VmState, write: WriteFn, buffer: &[u8]) {
+	let state: *mut VmState = data;
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		unsafe { write(state, buffer) }.unwrap();
+	}));
+	if let Err(p) = result {
+		data.panic = Some(p);
+	}
+}
+
+/// Writes to the print stream of the `SquirrelData<P, E>` a state belongs to.
+unsafe fn write_print<P: Write, E>(state: *mut VmState, buffer: &[u8]) -> io::Result<()> {
+	(*(state as *mut SquirrelData<P, E>)).print.write_all(buffer)
+}
+
+/// Writes to the error stream of the `SquirrelData<P, E>` a state belongs to.
+unsafe fn write_error<P, E: Write>(state: *mut VmState, buffer: &[u8]) -> io::Result<()> {
+	(*(state as *mut SquirrelData<P, E>)).error.write_all(buffer)
 }
 
 /// Handles compiler errors
 extern fn err_handler(v: ffi::HSQUIRRELVM, desc: *const ffi::SQChar, source: *const ffi::SQChar, line: ffi::SQInteger, column: ffi::SQInteger) {
-	unsafe {
-		let desc = from_utf8(CStr::from_ptr(desc).to_bytes()).unwrap().to_string();
-		let source = from_utf8(CStr::from_ptr(source).to_bytes()).unwrap().to_string();
-
-		let error: &mut Option<CompilerError> = mem::transmute(ffi::sq_getforeignptr(v));
-		*error = Some(CompilerError {
-			desc: desc,
-			source: source,
-			line: line,
-			column: column
+	if let Some(data) = unsafe { vm_state(v) } {
+		let result = panic::catch_unwind(|| {
+			unsafe {
+				CompilerError {
+					desc: String::from_utf8_lossy(CStr::from_ptr(desc).to_bytes()).into_owned(),
+					source: String::from_utf8_lossy(CStr::from_ptr(source).to_bytes()).into_owned(),
+					line: line,
+					column: column,
+					line_text: None
+				}
+			}
 		});
+		match result {
+			Ok(error) => data.diagnostics.push(error),
+			Err(p) => data.panic = Some(p)
+		}
 	}
 }
 
+/// State passed to `read_fn` while compiling
+struct ReadState<'a, C: 'a> {
+	chars: &'a mut C,
+	/// The source read so far, kept for diagnostics
+	text: String,
+	panic: Option<Box<dyn Any + Send>>
+}
+
 /// Reads from an Iterator over chars stored at a pointer
 extern fn read_fn<C: Iterator<Item = char>>(ptr: ffi::SQUserPointer) -> ffi::SQInteger {
-	let chars: &mut C = unsafe { mem::transmute(ptr) };
-	let next = chars.next();
-	
-	match next {
-		Some(c) => c as ffi::SQInteger,
-		None => 0
+	let state: &mut ReadState<C> = unsafe { &mut *(ptr as *mut ReadState<C>) };
+	if state.panic.is_some() {
+		return 0;
+	}
+	
+	let chars = &mut state.chars;
+	match panic::catch_unwind(AssertUnwindSafe(|| chars.next())) {
+		Ok(Some(c)) => {
+			state.text.push(c);
+			c as ffi::SQInteger
+		},
+		Ok(None) => 0,
+		Err(p) => {
+			// Report the end of the script and re-raise once the compiler returns
+			state.panic = Some(p);
+			0
+		}
+	}
+}
+
+/// State passed to `read_closure_fn` and `write_closure_fn`
+struct IoState<'a, S: 'a> {
+	stream: &'a mut S,
+	error: Option<io::Error>,
+	panic: Option<Box<dyn Any + Send>>
+}
+
+/// Writes serialized closures to a `Write` stored at a pointer
+extern fn write_closure_fn<W: Write>(ptr: ffi::SQUserPointer, data: ffi::SQUserPointer, size: ffi::SQInteger) -> ffi::SQInteger {
+	let state: &mut IoState<W> = unsafe { &mut *(ptr as *mut IoState<W>) };
+	let data = unsafe { slice::from_raw_parts(data as *const u8, size as usize) };
+	
+	let stream = &mut state.stream;
+	match panic::catch_unwind(AssertUnwindSafe(|| stream.write_all(data))) {
+		Ok(Ok(())) => size,
+		Ok(Err(e)) => {
+			state.error = Some(e);
+			-1
+		},
+		Err(p) => {
+			state.panic = Some(p);
+			-1
+		}
+	}
+}
+
+/// Reads serialized closures from a `Read` stored at a pointer
+extern fn read_closure_fn<R: Read>(ptr: ffi::SQUserPointer, data: ffi::SQUserPointer, size: ffi::SQInteger) -> ffi::SQInteger {
+	let state: &mut IoState<R> = unsafe { &mut *(ptr as *mut IoState<R>) };
+	let data = unsafe { slice::from_raw_parts_mut(data as *mut u8, size as usize) };
+	
+	// Squirrel treats a short read as an error
+	let stream = &mut state.stream;
+	match panic::catch_unwind(AssertUnwindSafe(|| stream.read_exact(data))) {
+		Ok(Ok(())) => size,
+		Ok(Err(e)) => {
+			state.error = Some(e);
+			-1
+		},
+		Err(p) => {
+			state.panic = Some(p);
+			-1
+		}
+	}
+}
+
+/// Gets the data stored in the foreign pointer of a virtual machine, if it has any.
+///
+/// Threads created by scripts do not have a foreign pointer.
+///
+/// The state does not depend on the types of the streams, so callbacks shared by every thread of a machine
+/// can use it whichever thread they are called on.
+unsafe fn foreign_data<'a>(v: ffi::HSQUIRRELVM) -> Option<&'a mut VmState> {
+	let data = ffi::sq_getforeignptr(v) as *mut VmState;
+	if data.is_null() {
+		None
+	}
+	else {
+		Some(&mut *data)
+	}
+}
+
+/// The registry table slot holding a pointer to the state of the root virtual machine.
+const ROOT_KEY: &'static str = "squirrel.root";
+
+/// Gets the state of the root virtual machine that `v` belongs to, which outlives every thread.
+///
+/// Unlike `foreign_data`, this also works for threads created by scripts.
+unsafe fn root_state<'a>(v: ffi::HSQUIRRELVM) -> Option<&'a mut VmState> {
+	let top = ffi::sq_gettop(v);
+	let mut p = ptr::null_mut();
+	ffi::sq_pushregistrytable(v);
+	ffi::sq_pushstring(v, ROOT_KEY.as_ptr() as *const ffi::SQChar, ROOT_KEY.len() as ffi::SQInteger);
+	if ffi::SQ_SUCCEEDED(ffi::sq_rawget(v, -2)) {
+		ffi::sq_getuserpointer(v, -1, &mut p);
+	}
+	else {
+		ffi::sq_reseterror(v);
+	}
+	ffi::sq_settop(v, top);
+	(p as *mut VmState).as_mut()
+}
+
+/// Gets the state a callback running on `v` reports to.
+///
+/// Threads created by scripts have no foreign pointer, so they share the state of their root virtual machine.
+unsafe fn vm_state<'a>(v: ffi::HSQUIRRELVM) -> Option<&'a mut VmState> {
+	match foreign_data(v) {
+		Some(data) => Some(data),
+		None => root_state(v)
 	}
 }
 
+/// Writes to one of the streams of a `SquirrelData`, given a pointer to its state
+type WriteFn = unsafe fn(*mut VmState, &[u8]) -> io::Result<()>;
+
 /// Represents data relevant to a Squirrel virtual machine
+#[repr(C)]
 struct SquirrelData<P, E> {
-	#[allow(dead_code)]
-	compiler_error: Option<CompilerError>,
+	/// Must be the first field, so a pointer to the data is also a pointer to the state
+	state: VmState,
 	print: P,
 	error: E,
 }
 
+/// The part of `SquirrelData` that does not depend on the types of the streams
+struct VmState {
+	/// The virtual machine the data belongs to
+	vm: ffi::HSQUIRRELVM,
+	/// Errors reported by the compiler since the last compilation started
+	diagnostics: Vec<CompilerError>,
+	/// A panic raised inside a callback, re-raised when control returns to Rust
+	panic: Option<Box<dyn Any + Send>>,
+	/// The work scripts have done against the limits set with `set_limits`
+	budget: Option<limits::Budget>,
+	/// Cleared when the machine is closed. The flag of the root machine is shared with handles,
+	/// which must not release their objects once it is closed
+	alive: Rc<Cell<bool>>,
+	/// Writes to the print stream
+	print: WriteFn,
+	/// Writes to the error stream
+	error: WriteFn,
+}
+
+impl<P: Write, E: Write> SquirrelData<P, E> {
+	fn new(vm: ffi::HSQUIRRELVM, print: P, error: E) -> Box<SquirrelData<P, E>> {
+		Box::new(SquirrelData {
+			state: VmState {
+				vm: vm,
+				diagnostics: Vec::new(),
+				panic: None,
+				budget: None,
+				alive: Rc::new(Cell::new(true)),
+				print: write_print::<P, E>,
+				error: write_error::<P, E>
+			},
+			print: print,
+			error: error
+		})
+	}
+}
+
+/// Converts a path for use by the standard library.
+fn path_to_cstring(path: &Path) -> Result<CString, SquirrelError> {
+	match path.to_str().and_then(|p| CString::new(p).ok()) {
+		Some(p) => Ok(p),
+		None => Err(SquirrelError::Io {
+			path: path.to_path_buf(),
+			desc: "the path is not valid UTF-8 or contains a null character".to_string()
+		})
+	}
+}
+
+/// Converts a path for use by the standard library, checking that the file can be opened.
+///
+/// The standard library only reports that a file could not be opened, so this gives a more useful error.
+fn open_path(path: &Path) -> Result<CString, SquirrelError> {
+	if let Err(e) = File::open(path) {
+		return Err(SquirrelError::Io {
+			path: path.to_path_buf(),
+			desc: e.to_string()
+		});
+	}
+	path_to_cstring(path)
+}
+
 fn get_result<T, E>(r: ffi::SQRESULT, t: T, e: E) -> Result<T, E> {
 	if ffi::SQ_SUCCEEDED(r) {
 		Ok(t)
@@ -103,41 +355,37 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	/// let vm = SquirrelVM::new(1024, stdout(), stderr());
 	/// ```
 	pub fn new(initial_stack: isize, print_stream: P, error_stream: E) -> SquirrelVM<P, E> {
-		let data = Box::new(SquirrelData {
-			print: print_stream,
-			error: error_stream,
-			compiler_error: None
-		});
-		
 		let vm = unsafe { ffi::sq_open(initial_stack) };
+		let mut data = SquirrelData::new(vm, print_stream, error_stream);
 		unsafe {
+			// Every thread finds the root's state through the registry, which they share
+			let state: *mut VmState = &mut data.state;
+			ffi::sq_pushregistrytable(vm);
+			ffi::sq_pushstring(vm, ROOT_KEY.as_ptr() as *const ffi::SQChar, ROOT_KEY.len() as ffi::SQInteger);
+			ffi::sq_pushuserpointer(vm, state as ffi::SQUserPointer);
+			ffi::sq_rawset(vm, -3);
+			ffi::sq_poptop(vm);
+			
 			// Turns the box into a raw pointer - the structure is freed when dropped
 			ffi::sq_setforeignptr(vm, mem::transmute(data));
 			ffi::sq_setprintfunc(vm, shim_print_fn, shim_err_fn);
 			ffi::sq_setcompilererrorhandler(vm, err_handler);
 			
-			shim_set_print_callback(print_fn::<P, E>);
-			shim_set_err_callback(err_fn::<P, E>);
+			shim_set_print_callback(print_fn);
+			shim_set_err_callback(err_fn);
 		}
 		SquirrelVM(vm, PhantomData)
 	}
 	/// Creates a new Squirrel virtual machine that is a friend of this machine.
-	pub fn new_thread<Q, F>(&self, initial_stack: isize, print_stream: Q, error_stream: F) -> SquirrelVM<Q, F> {
-		let data = Box::new(SquirrelData {
-			print: print_stream,
-			error: error_stream,
-			compiler_error: None
-		});
-		
+	///
+	/// The print functions and compiler error handler are shared with this machine, and find the streams
+	/// and diagnostics of whichever thread they are called on.
+	pub fn new_thread<Q: Write + Sync, F: Write + Sync>(&self, initial_stack: isize, print_stream: Q, error_stream: F) -> SquirrelVM<Q, F> {
 		let vm = unsafe { ffi::sq_newthread(self.0, initial_stack) };
+		let data = SquirrelData::new(vm, print_stream, error_stream);
 		unsafe {
 			// Turns the box into a raw pointer - the structure is freed when dropped
 			ffi::sq_setforeignptr(vm, mem::transmute(data));
-			ffi::sq_setprintfunc(vm, shim_print_fn, shim_err_fn);
-			ffi::sq_setcompilererrorhandler(vm, err_handler);
-			
-			shim_set_print_callback(print_fn::<P, E>);
-			shim_set_err_callback(err_fn::<P, E>);
 		}
 		SquirrelVM(vm, PhantomData)
 	}
@@ -147,15 +395,51 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 			ffi::sq_seterrorhandler(self.0);
 		}
 	}
+	/// Sets a Rust closure to be the runtime error handler.
+	///
+	/// The closure is called with the thrown value and the call stack whenever a call made with `raise_error` fails.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// vm.on_runtime_error(|e| {
+	/// 	println!("{} in '{}' on line {}", e.value, e.source, e.line);
+	/// });
+	/// ```
+	pub fn on_runtime_error<F: Fn(&RuntimeError) + 'static>(&mut self, handler: F) {
+		self.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+			handler(&debug::capture_error(vm));
+		}, 0);
+		self.set_error_handler();
+	}
+	/// Sets the runtime error handler to one that reports errors like the standard library's.
+	///
+	/// This prints the error and the call stack, including locals, to the error stream.
+	/// Thrown tables, arrays and instances are shown with their contents, as rendered by `inspect`.
+	pub fn set_default_error_handler(&mut self) {
+		self.new_internal_closure(|vm: &mut SquirrelVM<P, E>| {
+			let error = debug::capture_error(vm);
+			// Messages are shown without quotes, as the standard library does
+			let desc = match error.value {
+				Value::String(ref s) => s.clone(),
+				_ => error.rendered.clone()
+			};
+			let report = format!("\nAN ERROR HAS OCCURRED [{}]\n{}", desc, render_call_stack(&error.backtrace));
+			vm.write_error(report.as_bytes());
+		}, 0);
+		self.set_error_handler();
+	}
 	pub fn suspend(&mut self) -> Result<(), ()> {
 		get_result(unsafe {
 			ffi::sq_suspendvm(self.0)
 		}, (), ())
 	}
 	pub fn wake_up(&mut self, resumed_return: bool, return_value: bool, raise_error: bool, throw_error: bool) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_wakeupvm(self.0, resumed_return as ffi::SQBool, return_value as ffi::SQBool, raise_error as ffi::SQBool, throw_error as ffi::SQBool)
-		}, (), ())
+		self.start_run();
+		let result = unsafe { ffi::sq_wakeupvm(self.0, resumed_return as ffi::SQBool, return_value as ffi::SQBool, raise_error as ffi::SQBool, throw_error as ffi::SQBool) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	pub fn get_vm_state(&self) -> State {
 		match unsafe { ffi::sq_getvmstate(self.0) } {
@@ -173,19 +457,28 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	/// `chars` is an iterator over the characters in the script.
 	/// `name` is symbolic name of the script (used to provide useful runtime debugging information).
 	pub fn compile<C: Iterator<Item = char>>(&mut self, chars: &mut C, name: &str) -> Result<(), CompilerError> {
-		let name = CString::new(name).unwrap();
+		try!(diagnostics::check_nulls("", name));
+		let c_name = CString::new(name).unwrap();
+		let mut state = ReadState {
+			chars: chars,
+			text: String::new(),
+			panic: None
+		};
+		self.clear_diagnostics();
 		let result = unsafe {
-			ffi::sq_compile(self.0, read_fn::<C>, mem::transmute(chars), mem::transmute(name.as_ptr()), 1)
+			ffi::sq_compile(self.0, read_fn::<C>, &mut state as *mut ReadState<C> as ffi::SQUserPointer, c_name.as_ptr(), 1)
 		};
 		
+		if let Some(p) = state.panic {
+			panic::resume_unwind(p);
+		}
+		self.check_panic();
+		
 		if ffi::SQ_SUCCEEDED(result) {
 			Ok(())
 		}
 		else {
-			let data: &mut Option<CompilerError> = unsafe { mem::transmute(ffi::sq_getforeignptr(self.0)) };
-			let error = data.clone().unwrap();
-			*data = None;
-			Err(error)
+			Err(self.take_diagnostics(name, &state.text).remove(0))
 		}
 	}
 	/// Compiles a Squirrel script stored in a `&str`.
@@ -193,24 +486,157 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	/// `src` is a `&str` containing the sourc code of the script.
 	/// `name` is symbolic name of the script (used to provide useful runtime debugging information).
 	pub fn compile_str(&mut self, src: &str, name: &str) -> Result<(), CompilerError> {
+		self.compile_buffer(src, name).map_err(|mut errors| errors.remove(0))
+	}
+	/// Checks a script for syntax errors without leaving a closure on the stack.
+	///
+	/// The Squirrel compiler stops at the first error, so to report as many errors as possible
+	/// the offending line is blanked out and the script is compiled again.
+	/// Errors after the first may be caused by earlier ones.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// # let src = "local x = ;";
+	/// if let Err(errors) = vm.check_syntax(src, "script.nut") {
+	/// 	for e in errors {
+	/// 		print!("{}", e.render());
+	/// 	}
+	/// }
+	/// ```
+	pub fn check_syntax(&mut self, src: &str, name: &str) -> Result<(), Vec<CompilerError>> {
+		const MAX_ERRORS: usize = 32;
+		
+		let mut errors: Vec<CompilerError> = Vec::new();
+		let mut text = src.to_string();
+		loop {
+			match self.compile_buffer(&text, name) {
+				Ok(()) => {
+					self.pop(1);
+					break;
+				},
+				Err(found) => {
+					let mut error = found.into_iter().next().unwrap();
+					diagnostics::attach_source(&mut error, src);
+					
+					// Only continue while errors move forward through the script
+					let progressed = errors.last().is_none_or(|last| error.line > last.line);
+					if !progressed {
+						break;
+					}
+					text = diagnostics::blank_line(&text, error.line);
+					errors.push(error);
+					if errors.len() >= MAX_ERRORS {
+						break;
+					}
+				}
+			}
+		}
+		
+		if errors.is_empty() {
+			Ok(())
+		}
+		else {
+			Err(errors)
+		}
+	}
+	/// Compiles a buffer, returning every error reported by the compiler.
+	fn compile_buffer(&mut self, src: &str, name: &str) -> Result<(), Vec<CompilerError>> {
+		if let Err(error) = diagnostics::check_nulls(src, name) {
+			return Err(vec![error]);
+		}
 		let len = src.len();
-		let src = CString::new(src).unwrap();
-		let name = CString::new(name).unwrap();
+		let c_src = CString::new(src).unwrap();
+		let c_name = CString::new(name).unwrap();
 		
+		self.clear_diagnostics();
 		let result = unsafe {
-			ffi::sq_compilebuffer(self.0, mem::transmute(src.as_ptr()), len as isize, mem::transmute(name.as_ptr()), 1)
+			ffi::sq_compilebuffer(self.0, c_src.as_ptr(), len as isize, c_name.as_ptr(), 1)
 		};
+		self.check_panic();
 		
 		if ffi::SQ_SUCCEEDED(result) {
 			Ok(())
 		}
 		else {
-			let data: &mut Option<CompilerError> = unsafe { mem::transmute(ffi::sq_getforeignptr(self.0)) };
-			let error = data.clone().unwrap();
-			*data = None;
-			Err(error)
+			Err(self.take_diagnostics(name, src))
+		}
+	}
+	/// Clears errors reported by an earlier compilation.
+	fn clear_diagnostics(&mut self) {
+		if let Some(data) = unsafe { foreign_data(self.0) } {
+			data.diagnostics.clear();
+		}
+	}
+	/// Takes the errors reported by the compiler error handler, attaching the offending lines of `src`.
+	///
+	/// This always returns at least one error.
+	fn take_diagnostics(&mut self, name: &str, src: &str) -> Vec<CompilerError> {
+		let mut errors = match unsafe { foreign_data(self.0) } {
+			Some(data) => mem::take(&mut data.diagnostics),
+			None => Vec::new()
+		};
+		if errors.is_empty() {
+			errors.push(CompilerError {
+				desc: "compilation failed".to_string(),
+				source: name.to_string(),
+				line: 0,
+				column: 0,
+				line_text: None
+			});
+		}
+		for error in &mut errors {
+			diagnostics::attach_source(error, src);
+		}
+		errors
+	}
+	/// Re-raises a panic caught in a callback since the last call into the virtual machine.
+	fn check_panic(&self) {
+		if let Some(p) = unsafe { foreign_data(self.0) }.and_then(|data| data.panic.take()) {
+			panic::resume_unwind(p);
+		}
+	}
+	/// Restarts the time limit if no script is running, so that each call made from Rust gets the full time.
+	///
+	/// A suspended machine is not running, as it is the host that wakes it up.
+	fn start_run(&mut self) {
+		let mut si = ffi::SQStackInfos {
+			funcname: ptr::null(),
+			source: ptr::null(),
+			line: 0
+		};
+		let running = ffi::SQ_SUCCEEDED(unsafe { ffi::sq_stackinfos(self.0, 0, &mut si) });
+		if running && unsafe { ffi::sq_getvmstate(self.0) } != ffi::SQ_VMSTATE_SUSPENDED {
+			return;
+		}
+		if let Some(budget) = unsafe { foreign_data(self.0) }.and_then(|data| data.budget.as_mut()) {
+			budget.restart_clock();
+		}
+	}
+	/// Writes to the error stream, as the `stderr` print function does.
+	fn write_error(&mut self, buffer: &[u8]) {
+		if let Some(data) = unsafe { foreign_data(self.0) } {
+			let write = data.error;
+			write_stream(data, write, buffer);
+		}
+	}
+	/// Sets the limits on how much work scripts may do, restarting the count.
+	///
+	/// The time limit applies to each call made from Rust while no script is running, such as `call`, `resume`
+	/// and `do_file`, rather than to the lifetime of the machine.
+	///
+	/// Limits are only checked when a script calls a native closure. Squirrel cannot interrupt a script
+	/// between instructions, so a loop that never calls a native closure cannot be stopped, and a script
+	/// can run past its time limit for as long as it goes without calling one.
+	/// Native closures called by scripts running in other threads are not counted, and neither are
+	/// the error handlers and library functions this crate adds itself.
+	pub fn set_limits(&mut self, limits: Limits) {
+		if let Some(data) = unsafe { foreign_data(self.0) } {
+			data.budget = if limits.is_none() { None } else { Some(limits::Budget::new(limits)) };
 		}
 	}
+	
 	/// Enables or disables debug info.
 	pub fn set_debug_info(&mut self, enable: bool) {
 		unsafe {
@@ -255,13 +681,20 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	}
 	
 	pub fn cmp(&self) -> isize {
-		unsafe { ffi::sq_cmp(self.0) }
+		let result = unsafe { ffi::sq_cmp(self.0) };
+		self.check_panic();
+		result
 	}
 	
 	pub fn move_item<Q, F>(&mut self, dest: &mut SquirrelVM<Q, F>, idx: isize) {
 		unsafe { ffi::sq_move(dest.0, self.0, idx); }
 	}
 	
+	/// Creates a guard that restores the top of the stack to its current value when dropped.
+	pub fn guard(&mut self) -> StackGuard<'_, P, E> {
+		StackGuard::new(self)
+	}
+	
 	/* Object creation handling */
 	
 	pub fn new_user_data<'a, T>(&mut self) -> &'a mut T {
@@ -282,9 +715,44 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		unsafe { ffi::sq_newarray(self.0, size as ffi::SQInteger); }
 	}
 	
-	pub fn new_closure<F: Fn(SquirrelVM<P, E>)>(&mut self, func: F, n_free_vars: usize) {
-		// Can't think of how to do this yet
-		unimplemented!();
+	/// Pushes a native closure that calls `func` when invoked by a script.
+	///
+	/// `n_free_vars` values are popped from the stack and bound to the closure as free variables.
+	/// Inside `func` the arguments are at indices `1` (`this`) up to the top of the stack, followed by the free variables.
+	/// Returning `Ok(value)` returns `value` to the script, and returning `Err(error)` throws `error` so it can be caught by a script.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// vm.new_closure(|vm| -> Result<isize, SquirrelError> {
+	/// 	let a: isize = try!(vm.read(2));
+	/// 	let b: isize = try!(vm.read(3));
+	/// 	Ok(a + b)
+	/// }, 0);
+	/// ```
+	pub fn new_closure<F, R>(&mut self, func: F, n_free_vars: usize)
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.push_native(func, n_free_vars, native::call_native::<P, E, F, R>);
+	}
+	/// Pushes a native closure the crate uses itself, which is not charged to the limits.
+	fn new_internal_closure<F, R>(&mut self, func: F, n_free_vars: usize)
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		self.push_native(func, n_free_vars, native::call_internal::<P, E, F, R>);
+	}
+	/// Pushes a native closure that runs `func` through `call`.
+	fn push_native<F, R>(&mut self, func: F, n_free_vars: usize, call: ffi::SQFUNCTION)
+		where F: Fn(&mut SquirrelVM<P, E>) -> R + 'static, R: NativeReturn
+	{
+		unsafe {
+			// The userdata owns the boxed closure and frees it when collected
+			let ud = ffi::sq_newuserdata(self.0, mem::size_of::<*mut F>() as ffi::SQUnsignedInteger) as *mut *mut F;
+			*ud = Box::into_raw(Box::new(func));
+			ffi::sq_setreleasehook(self.0, -1, native::release_native::<F>);
+			ffi::sq_newclosure(self.0, call, (n_free_vars + 1) as ffi::SQUnsignedInteger);
+		}
 	}
 	
 	pub fn set_params_check(&mut self, n_params_check: isize, type_mask: &str) -> Result<(), ()> {
@@ -325,12 +793,41 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		unsafe { ffi::sq_pushnull(self.0); }
 	}
 	
+	/// Pushes any value that can be converted to a Squirrel value.
+	pub fn push_value<T: ToSquirrel>(&mut self, value: T) {
+		value.push_to(self);
+	}
+	
+	/// Reads the value at `idx` as a Rust value, leaving the stack unchanged.
+	pub fn read<T: FromSquirrel>(&self, idx: isize) -> Result<T, SquirrelError> {
+		T::read(self, idx)
+	}
+	
+	/// Reads the value at `idx` as a `Value` of whatever type it has.
+	///
+	/// Reference types such as tables and closures are held by a `Handle`, so they stay alive after being popped.
+	/// Unreadable values are returned as `Value::Null`.
+	pub fn type_of(&self, idx: isize) -> Value {
+		self.read(idx).unwrap_or(Value::Null)
+	}
+	
 	//pub fn sq_gettype(v: HSQUIRRELVM, idx: SQInteger) -> SQObjectType;
 	//pub fn sq_typeof(v: HSQUIRRELVM, idx: SQInteger) -> SQRESULT;
 	//pub fn sq_getsize(v: HSQUIRRELVM, idx: SQInteger) -> SQInteger;
-	//pub fn sq_gethash(v: HSQUIRRELVM, idx: SQInteger) -> SQHash;
+	/// Gets the hash Squirrel uses for the value at `idx` when it is a table key.
+	///
+	/// Reference types hash by identity, so two tables with the same contents have different hashes.
+	pub fn get_hash(&self, idx: isize) -> usize {
+		unsafe { ffi::sq_gethash(self.0, idx) }
+	}
+	
 	//pub fn sq_getbase(v: HSQUIRRELVM, idx: SQInteger) -> SQRESULT;
-	//pub fn sq_instanceof(v: HSQUIRRELVM) -> SQBool;
+	
+	/// Checks whether the instance at -2 is an instance of the class at -1, or of a class derived from it.
+	pub fn instance_of(&self) -> bool {
+		unsafe { ffi::sq_instanceof(self.0) != 0 }
+	}
+	
 	//pub fn sq_tostring(v: HSQUIRRELVM, idx: SQInteger) -> SQRESULT;
 	//pub fn sq_tobool(v: HSQUIRRELVM, idx: SQInteger, b: *mut SQBool) -> c_void;
 	//pub fn sq_getstring(v: HSQUIRRELVM, idx: SQInteger, c: *mut *const SQChar) -> SQRESULT;
@@ -372,6 +869,21 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		unsafe { ffi::sq_pushregistrytable(self.0); }
 	}
 	
+	/// Pushes the table stored in the registry table under `key`, creating it if needed.
+	fn push_registry_entry(&mut self, key: &str) {
+		self.push_registry_table();
+		self.push_value(key);
+		if self.raw_get(-2).is_err() {
+			self.reset_error();
+			self.push_value(key);
+			self.new_table();
+			let _ = self.new_slot(-3, false);
+			self.push_value(key);
+			let _ = self.raw_get(-2);
+		}
+		self.remove(-2);
+	}
+	
 	pub fn push_const_table(&mut self) {
 		unsafe { ffi::sq_pushconsttable(self.0); }
 	}
@@ -389,27 +901,27 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	}
 	
 	pub fn new_slot(&mut self, idx: isize, bstatic: bool) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_newslot(self.0, idx, bstatic as ffi::SQBool)
-		}, (), ())
+		let result = unsafe { ffi::sq_newslot(self.0, idx, bstatic as ffi::SQBool) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
 	pub fn delete_slot(&mut self, idx: isize, push_val: bool) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_deleteslot(self.0, idx, push_val as ffi::SQBool)
-		}, (), ())
+		let result = unsafe { ffi::sq_deleteslot(self.0, idx, push_val as ffi::SQBool) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
 	pub fn set(&mut self, idx: isize) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_set(self.0, idx)
-		}, (), ())
+		let result = unsafe { ffi::sq_set(self.0, idx) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
 	pub fn get(&mut self, idx: isize) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_get(self.0, idx)
-		}, (), ())
+		let result = unsafe { ffi::sq_get(self.0, idx) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
 	pub fn raw_set(&mut self, idx: isize) -> Result<(), ()> {
@@ -454,9 +966,10 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		}, (), ())
 	}
 	
-	pub fn sq_arrayresize(&mut self, idx: isize, new_size: usize) -> Result<(), ()> {
+	/// Changes the length of the array at `idx`, filling new elements with `null`.
+	pub fn array_resize(&mut self, idx: isize, new_size: usize) -> Result<(), ()> {
 		get_result(unsafe {
-			ffi::sq_arraypop(self.0, idx, new_size)
+			ffi::sq_arrayresize(self.0, idx, new_size as ffi::SQInteger)
 		}, (), ())
 	}
 	
@@ -478,6 +991,28 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		}, (), ())
 	}
 	
+	/// Gets a handle based interface to the array at `idx`.
+	///
+	/// It borrows the virtual machine, so the stack cannot be changed while it is in use.
+	pub fn array(&mut self, idx: isize) -> Result<ArrayRef<'_, P, E>, SquirrelError> {
+		if unsafe { ffi::sq_gettype(self.0, idx) } != ffi::SQObjectType::OT_ARRAY {
+			return Err(convert::mismatch(self.0, idx, "array"));
+		}
+		let handle = self.handle(idx);
+		ArrayRef::new(self, handle)
+	}
+	
+	/// Gets a handle based interface to the table at `idx`.
+	///
+	/// It borrows the virtual machine, so the stack cannot be changed while it is in use.
+	pub fn table(&mut self, idx: isize) -> Result<TableRef<'_, P, E>, SquirrelError> {
+		if unsafe { ffi::sq_gettype(self.0, idx) } != ffi::SQObjectType::OT_TABLE {
+			return Err(convert::mismatch(self.0, idx, "table"));
+		}
+		let handle = self.handle(idx);
+		TableRef::new(self, handle)
+	}
+	
 	pub fn set_delegate(&mut self, idx: isize) -> Result<(), ()> {
 		get_result(unsafe {
 			ffi::sq_setdelegate(self.0, idx)
@@ -525,21 +1060,33 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	/* Calls */
 	
 	pub fn call(&mut self, param_count: isize, retval: bool, raise_error: bool) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_call(self.0, param_count, retval as ffi::SQBool, raise_error as ffi::SQBool)
-		}, (), ())
+		self.start_run();
+		let result = unsafe { ffi::sq_call(self.0, param_count, retval as ffi::SQBool, raise_error as ffi::SQBool) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
 	pub fn resume(&mut self, retval: bool, raise_error: bool) -> Result<(), ()> {
-		get_result(unsafe {
-			ffi::sq_resume(self.0, retval as ffi::SQBool, raise_error as ffi::SQBool)
-		}, (), ())
+		self.start_run();
+		let result = unsafe { ffi::sq_resume(self.0, retval as ffi::SQBool, raise_error as ffi::SQBool) };
+		self.check_panic();
+		get_result(result, (), ())
 	}
 	
-	pub fn get_local(&mut self, level: usize, idx: usize) -> String {
-		unsafe {
-			from_utf8(CStr::from_ptr(ffi::sq_getlocal(self.0, level, idx)).to_bytes()).unwrap().to_string()
+	/// Gets the name and value of a local variable of the function at `level` in the call stack.
+	///
+	/// Returns `None` if there is no local at `idx`. The stack is left unchanged.
+	pub fn get_local(&mut self, level: usize, idx: usize) -> Option<(String, Value)> {
+		// sq_getlocal pushes the value of the local if it exists
+		let name = unsafe { ffi::sq_getlocal(self.0, level, idx) };
+		if name.is_null() {
+			return None;
 		}
+		
+		let name = unsafe { String::from_utf8_lossy(CStr::from_ptr(name).to_bytes()).into_owned() };
+		let value = self.read(-1).unwrap_or(Value::Null);
+		self.pop(1);
+		Some((name, value))
 	}
 	
 	pub fn get_callee(&mut self) -> Result<(), ()> {
@@ -579,8 +1126,37 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		}
 	}
 	
+	/// Gets the last error as a string and resets it, leaving the stack unchanged.
+	fn take_last_error(&mut self) -> String {
+		self.get_last_error();
+		let msg = match self.read(-1) {
+			Ok(Value::Null) | Err(_) => String::new(),
+			Ok(value) => value.to_string()
+		};
+		self.pop(1);
+		self.reset_error();
+		msg
+	}
+	
 	/* Raw object handling */
 	
+	/// Creates a handle to the object at `idx`, which keeps it alive until the handle is dropped.
+	pub fn handle(&self, idx: isize) -> Handle {
+		Handle::new(self, idx)
+	}
+	
+	/// Pushes the object a handle refers to.
+	///
+	/// Handles can be pushed onto any virtual machine sharing the state they were created in.
+	pub fn push_handle(&mut self, handle: &Handle) {
+		if handle.is_alive() {
+			unsafe { ffi::sq_pushobject(self.0, handle.raw()); }
+		}
+		else {
+			self.push_null();
+		}
+	}
+	
 	//pub fn sq_getstackobj(v: HSQUIRRELVM, idx: SQInteger, po: *mut HSQOBJECT) -> SQRESULT;
 	//pub fn sq_pushobject(v: HSQUIRRELVM, obj: HSQOBJECT) -> c_void;
 	//pub fn sq_addref(v: HSQUIRRELVM, po: *mut HSQOBJECT) -> c_void;
@@ -597,9 +1173,9 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	/* GC */
 	
 	pub fn collect_garbage(&mut self) -> isize {
-		unsafe {
-			ffi::sq_collectgarbage(self.0)
-		}
+		let result = unsafe { ffi::sq_collectgarbage(self.0) };
+		self.check_panic();
+		result
 	}
 	
 	pub fn resurrect_unreachable(&mut self) -> Result<(), ()> {
@@ -610,8 +1186,42 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	
 	/* Serialization */
 	
-	//pub fn write_closure(vm: HSQUIRRELVM, writef: SQWRITEFUNC, up: SQUserPointer) -> SQRESULT;
-	//pub fn sq_readclosure(vm: HSQUIRRELVM, readf: SQREADFUNC,up: SQUserPointer) -> SQRESULT;
+	/// Writes the closure at the top of the stack to `writer` as bytecode.
+	pub fn write_closure<W: Write>(&mut self, writer: &mut W) -> Result<(), SquirrelError> {
+		let mut state = IoState {
+			stream: writer,
+			error: None,
+			panic: None
+		};
+		let result = unsafe { ffi::sq_writeclosure(self.0, write_closure_fn::<W>, &mut state as *mut IoState<W> as ffi::SQUserPointer) };
+		self.closure_io_result(result, state)
+	}
+	
+	/// Reads a closure written by `write_closure` from `reader` and pushes it.
+	pub fn read_closure<R: Read>(&mut self, reader: &mut R) -> Result<(), SquirrelError> {
+		let mut state = IoState {
+			stream: reader,
+			error: None,
+			panic: None
+		};
+		let result = unsafe { ffi::sq_readclosure(self.0, read_closure_fn::<R>, &mut state as *mut IoState<R> as ffi::SQUserPointer) };
+		self.closure_io_result(result, state)
+	}
+	
+	/// Converts the result of reading or writing a closure, re-raising any panic.
+	fn closure_io_result<S>(&mut self, result: ffi::SQRESULT, state: IoState<S>) -> Result<(), SquirrelError> {
+		if let Some(p) = state.panic {
+			panic::resume_unwind(p);
+		}
+		if ffi::SQ_SUCCEEDED(result) {
+			return Ok(());
+		}
+		let desc = self.take_last_error();
+		Err(match state.error {
+			Some(e) => SquirrelError::Runtime(format!("{}: {}", desc, e)),
+			None => SquirrelError::Runtime(desc)
+		})
+	}
 	
 	/* Memory allocation */
 	
@@ -660,6 +1270,34 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 		})
 	}
 	
+	/// Captures the call stack, innermost frame first, including the locals of each frame.
+	///
+	/// Level 0 is the currently running function, so this is empty unless called from a native closure.
+	/// Use `render_call_stack` to format the result like the standard library's error handler.
+	pub fn backtrace(&mut self) -> Vec<Frame> {
+		debug::capture_backtrace(self, 0)
+	}
+	
+	/// Renders the value at `idx` as human-readable text, expanding the contents of tables, arrays, instances and classes.
+	///
+	/// Functions are shown with their names, and containers that contain themselves are marked as cycles.
+	/// The stack is left unchanged.
+	/// # Example output
+	/// ```text
+	/// {
+	///   items = [
+	///     1,
+	///     "two"
+	///   ],
+	///   name = "example",
+	///   update = function update
+	/// }
+	/// ```
+	pub fn inspect(&mut self, idx: isize, options: InspectOptions) -> String {
+		let idx = if idx < 0 { self.get_top() + idx + 1 } else { idx };
+		inspect::inspect(self, idx, &options)
+	}
+	
 	pub fn set_debug_hook(&mut self) {
 		unsafe { ffi::sq_setdebughook(self.0); }
 	}
@@ -668,6 +1306,247 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 	
 	//}
 	
+	/* Blobs */
+	
+	/// Pushes a new blob containing a copy of `data`.
+	///
+	/// The `stdblob` lib must be registered.
+	pub fn push_blob(&mut self, data: &[u8]) -> Result<(), SquirrelError> {
+		let top = self.get_top();
+		let p = unsafe { ffi::stdblob::sqstd_createblob(self.0, data.len() as ffi::SQInteger) };
+		// An empty blob may not have a buffer, so success is judged by whether the blob was pushed
+		if self.get_top() == top {
+			return Err(SquirrelError::Runtime("the blob lib is not registered".to_string()));
+		}
+		if !data.is_empty() {
+			unsafe { ptr::copy_nonoverlapping(data.as_ptr(), p as *mut u8, data.len()); }
+		}
+		Ok(())
+	}
+	
+	/// Borrows the contents of the blob at `idx` without copying.
+	///
+	/// The slice cannot outlive the borrow of the virtual machine (or of a `StackGuard`), so the blob cannot be popped while it is in use.
+	pub fn get_blob(&self, idx: isize) -> Result<&[u8], SquirrelError> {
+		let (p, len) = try!(self.blob_parts(idx));
+		Ok(unsafe { slice::from_raw_parts(p as *const u8, len) })
+	}
+	
+	/// Mutably borrows the contents of the blob at `idx` without copying.
+	pub fn get_blob_mut(&mut self, idx: isize) -> Result<&mut [u8], SquirrelError> {
+		let (p, len) = try!(self.blob_parts(idx));
+		Ok(unsafe { slice::from_raw_parts_mut(p as *mut u8, len) })
+	}
+	
+	/// Copies the contents of the blob at `idx` into a `Vec`.
+	pub fn blob_to_vec(&self, idx: isize) -> Result<Vec<u8>, SquirrelError> {
+		self.get_blob(idx).map(|data| data.to_vec())
+	}
+	
+	/// Gets the data pointer and size of the blob at `idx`.
+	fn blob_parts(&self, idx: isize) -> Result<(ffi::SQUserPointer, usize), SquirrelError> {
+		let mut p = ptr::null_mut();
+		if ffi::SQ_FAILED(unsafe { ffi::stdblob::sqstd_getblob(self.0, idx, &mut p) }) {
+			return Err(convert::mismatch(self.0, idx, "blob"));
+		}
+		let len = unsafe { ffi::stdblob::sqstd_getblobsize(self.0, idx) };
+		// Empty blobs may not have a buffer
+		if p.is_null() || len <= 0 {
+			return Ok((ptr::NonNull::<u8>::dangling().as_ptr() as ffi::SQUserPointer, 0));
+		}
+		Ok((p, len as usize))
+	}
+	
+	/* Streams */
+	
+	/// Pushes a `file` object that reads from, writes to and seeks `stream`.
+	///
+	/// Scripts can use it with the `stdio` lib's `file` API, such as `readn`, `writen`, `readblob`, `seek` and `eos`.
+	/// The `stdio` lib must be registered. The stream is dropped when the object is closed or collected.
+	///
+	/// This is only available on Linux, as it relies on glibc's `fopencookie`.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr, Cursor};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// vm.push_stream(Cursor::new(b"some bytes".to_vec())).unwrap();
+	/// ```
+	#[cfg(target_os = "linux")]
+	pub fn push_stream<S: Stream + 'static>(&mut self, stream: S) -> Result<(), SquirrelError> {
+		let file = stream::open(stream);
+		if file.is_null() {
+			return Err(SquirrelError::Runtime("could not open a handle for the stream".to_string()));
+		}
+		
+		let top = self.get_top();
+		let result = unsafe { ffi::stdio::sqstd_createfile(self.0, file as ffi::stdio::SQFILE, 1) };
+		if ffi::SQ_FAILED(result) || self.get_top() != top + 1 {
+			self.set_top(top);
+			unsafe { libc::fclose(file); }
+			return Err(SquirrelError::Runtime("the io lib is not registered".to_string()));
+		}
+		Ok(())
+	}
+	
+	/* Files */
+	
+	/// Loads a script from a file and pushes it as a closure.
+	///
+	/// Like the `stdio` lib's `loadfile`, both compiled bytecode and source files are accepted,
+	/// and source files may be UTF-8 (with or without a byte order mark) or UTF-16.
+	/// Compiler errors are returned as `SquirrelError::Compile`.
+	pub fn load_file<Q: AsRef<Path>>(&mut self, path: Q) -> Result<(), SquirrelError> {
+		let path = path.as_ref();
+		let c_path = try!(open_path(path));
+		
+		self.clear_diagnostics();
+		let result = unsafe { ffi::stdio::sqstd_loadfile(self.0, c_path.as_ptr(), 1) };
+		self.check_panic();
+		
+		if ffi::SQ_SUCCEEDED(result) {
+			Ok(())
+		}
+		else {
+			Err(self.file_error(path))
+		}
+	}
+	
+	/// Loads a script from a file and runs it with the root table as `this`, returning its result.
+	///
+	/// Files are loaded in the same way as `load_file`. The root table is pushed for the call and popped
+	/// along with the result, so the stack is left unchanged.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # fn load() -> Result<(), SquirrelError> {
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// let config: Option<String> = try!(vm.do_file("config.nut"));
+	/// # Ok(())
+	/// # }
+	/// ```
+	pub fn do_file<T: FromSquirrel, Q: AsRef<Path>>(&mut self, path: Q) -> Result<T, SquirrelError> {
+		let path = path.as_ref();
+		let c_path = try!(open_path(path));
+		
+		let top = self.get_top();
+		// The script is called with the value below it on the stack as `this`
+		self.push_root_table();
+		self.clear_diagnostics();
+		self.start_run();
+		let result = unsafe { ffi::stdio::sqstd_dofile(self.0, c_path.as_ptr(), 1, 1) };
+		self.check_panic();
+		
+		let value = if ffi::SQ_SUCCEEDED(result) {
+			self.read(-1)
+		}
+		else {
+			Err(self.file_error(path))
+		};
+		self.set_top(top);
+		value
+	}
+	
+	/// Writes the closure at the top of the stack to a file as bytecode.
+	///
+	/// The file can be loaded again with `load_file`.
+	pub fn write_closure_to_file<Q: AsRef<Path>>(&mut self, path: Q) -> Result<(), SquirrelError> {
+		let path = path.as_ref();
+		let c_path = try!(path_to_cstring(path));
+		
+		let result = unsafe { ffi::stdio::sqstd_writeclosuretofile(self.0, c_path.as_ptr()) };
+		if ffi::SQ_SUCCEEDED(result) {
+			Ok(())
+		}
+		else {
+			Err(SquirrelError::Io {
+				path: path.to_path_buf(),
+				desc: self.take_last_error()
+			})
+		}
+	}
+	
+	/// Loads a script held in memory and pushes it as a closure.
+	///
+	/// `data` may be compiled bytecode or source code, decoded in the same way as `load_file`.
+	pub fn load_bytes(&mut self, data: &[u8], name: &str) -> Result<(), SquirrelError> {
+		match diagnostics::decode_source(data) {
+			Some(src) => self.compile_buffer(&src, name).map_err(|mut errors| SquirrelError::Compile(errors.remove(0))),
+			None => self.read_closure(&mut &data[..])
+		}
+	}
+	
+	/// Makes the `stdio` lib's file functions use `vfs` instead of the real disk.
+	///
+	/// `loadfile`, `dofile` and `writeclosuretofile` are replaced in the root table and the constructor of the
+	/// `file` class is replaced, so this must be called after the `stdio` lib is registered, and supersedes any
+	/// `StdLibs` path checks. The class itself is changed because scripts can also reach it through
+	/// `stdout.getclass()` or an open file.
+	/// Files opened by the host, such as with `push_stream`, are passed through unchanged.
+	/// Paths can only be opened on Linux; elsewhere `file` is removed and opening a path always fails.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// let vfs = MemoryVfs::new();
+	/// vfs.insert("init.nut", b"return 1 + 2".to_vec()).unwrap();
+	/// vm.set_vfs(vfs).unwrap();
+	/// vm.compile_str("return dofile(\"init.nut\")", "main").unwrap();
+	/// ```
+	pub fn set_vfs<V: Vfs + 'static>(&mut self, vfs: V) -> Result<(), SquirrelError> {
+		vfs::install(self, Rc::new(vfs))
+	}
+	
+	/// Adds a `require(name)` function to the root table that loads modules found by `resolver`.
+	///
+	/// Each module is run the first time it is required, and its exports are cached in the registry table,
+	/// so later calls return the same value. Names are normalized first, so `./a`, `a` and `a.nut` are the same module.
+	/// Requiring a module while it is still loading is an error.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// let mut embedded = EmbeddedResolver::new();
+	/// embedded.insert("greet", &b"return function(name) { return \"hello \" + name; }"[..]);
+	/// let resolvers: Vec<Box<dyn ModuleResolver<_, _>>> = vec![Box::new(embedded), Box::new(FsResolver::new().path("scripts"))];
+	/// vm.set_module_resolver(resolvers).unwrap();
+	/// vm.compile_str("local greet = require(\"greet\"); print(greet(\"world\"));", "main").unwrap();
+	/// ```
+	pub fn set_module_resolver<R: ModuleResolver<P, E> + 'static>(&mut self, resolver: R) -> Result<(), SquirrelError>
+		where P: 'static, E: 'static
+	{
+		require::install(self, Rc::new(resolver))
+	}
+	
+	/// Installs a module, recording its name and version.
+	///
+	/// Returns `SquirrelError::AlreadyInstalled` if a module with the same name has already been installed.
+	pub fn install<M: SquirrelModule<P, E>>(&mut self, module: M) -> Result<(), SquirrelError> {
+		module::install(self, &module)
+	}
+	
+	/// Lists the modules installed with `install`, sorted by name.
+	pub fn installed_modules(&mut self) -> Vec<ModuleInfo> {
+		module::installed(self)
+	}
+	
+	/// Builds the error for a failure while loading or running the file at `path`.
+	fn file_error(&mut self, path: &Path) -> SquirrelError {
+		let has_diagnostics = unsafe { foreign_data(self.0) }.is_some_and(|data| !data.diagnostics.is_empty());
+		if has_diagnostics {
+			let src = diagnostics::read_source(path).unwrap_or_default();
+			let error = self.take_diagnostics(&path.to_string_lossy(), &src).remove(0);
+			self.reset_error();
+			SquirrelError::Compile(error)
+		}
+		else {
+			SquirrelError::Runtime(self.take_last_error())
+		}
+	}
+	
 	/* stdlib */
 	
 	/// Registers the `stdblob` lib for use with this virtual machine.
@@ -710,6 +1589,42 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 			Err(())
 		}
 	}
+	/// Formats values exactly as the `format` function provided by the `stdstring` lib does.
+	///
+	/// The stack is left unchanged. The `stdstring` lib does not need to be registered.
+	/// # Example
+	/// ```
+	/// # use squirrel::*;
+	/// # use std::io::{stdout, stderr};
+	/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+	/// let s = vm.format("%d items", &[Value::Integer(3)]).unwrap();
+	/// assert_eq!(s, "3 items");
+	/// ```
+	pub fn format(&mut self, format: &str, values: &[Value]) -> Result<String, SquirrelError> {
+		let top = self.get_top();
+		self.push_value(format);
+		for value in values {
+			self.push_value(value);
+		}
+		
+		let mut len = 0;
+		let mut output = ptr::null_mut();
+		let result = unsafe { ffi::stdstring::sqstd_format(self.0, top + 1, &mut len, &mut output) };
+		
+		let formatted = if ffi::SQ_SUCCEEDED(result) {
+			// The output is in the scratchpad, which the virtual machine owns
+			let bytes = unsafe { slice::from_raw_parts(output as *const u8, len as usize) };
+			Ok(String::from_utf8_lossy(bytes).into_owned())
+		}
+		else {
+			Err(SquirrelError::Format {
+				format: format.to_string(),
+				desc: self.take_last_error()
+			})
+		};
+		self.set_top(top);
+		formatted
+	}
 	/// Registers the `stdsystem` lib for use with this virtual machine.
 	pub fn register_system_lib(&mut self) -> Result<(), ()> {
 		let result = unsafe { ffi::stdsystem::sqstd_register_systemlib(self.0) };
@@ -725,36 +1640,12 @@ impl<P: Write + Sync, E: Write + Sync> SquirrelVM<P, E> {
 impl<P, E> Drop for SquirrelVM<P, E> {
 	fn drop(&mut self) {
 		// Get a box so we free the memory
-		let _: Box<SquirrelData<P, E>> = unsafe { mem::transmute(ffi::sq_getforeignptr(self.0)) };
+		let data: Box<SquirrelData<P, E>> = unsafe { mem::transmute(ffi::sq_getforeignptr(self.0)) };
+		data.state.alive.set(false);
 		unsafe { ffi::sq_close(self.0) }
 	}
 }
 
-/// Represents a compiler error thrown by a SquirrelVM.
-#[derive(Debug, Clone)]
-pub struct CompilerError {
-	/// A description of the error.
-	pub desc: String,
-	/// The source name of the script that caused the error.
-	pub source: String,
-	/// The line the error is on.
-	pub line: isize,
-	/// The column the error is on.
-	pub column: isize
-}
-
-impl fmt::Display for CompilerError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "Error: {} in '{}' on line '{}', column {}", self.desc, self.source, self.line, self.column)
-	}
-}
-
-impl Error for CompilerError {
-    fn description(&self) -> &str {
-		&self.desc[..]
-	}
-}
-
 /// Represents the state of a virtual machine
 #[derive(Debug, Clone)]
 pub enum State {
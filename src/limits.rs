@@ -0,0 +1,129 @@
+//! Resource limits for scripts.
+//!
+//! Squirrel has no way to interrupt a running script, so limits are checked each time a script calls a native closure.
+//! A script that does not call any native closures is never stopped, however long it runs.
+//! Only closures pushed with `SquirrelVM::new_closure` are counted, including class methods and metamethods.
+//! The standard library and the closures this crate adds itself, such as error handlers and the functions
+//! installed by `set_vfs`, `set_module_resolver` and `StdLibs`, are not.
+//!
+//! Once the native call limit is exceeded, every later native call throws, until the limits are set again.
+//! The time limit restarts with each call made from Rust while no script is running.
+
+use std::time::{Duration, Instant};
+
+/// Limits on how much work scripts may do.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// # use std::time::Duration;
+/// vm.set_limits(Limits::none().max_native_calls(10000).timeout(Duration::from_secs(5)));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+	max_native_calls: Option<u64>,
+	timeout: Option<Duration>
+}
+
+impl Limits {
+	/// Places no limits on scripts.
+	pub fn none() -> Limits {
+		Limits::default()
+	}
+
+	/// Limits the number of calls scripts may make to native closures.
+	pub fn max_native_calls(mut self, calls: u64) -> Limits {
+		self.max_native_calls = Some(calls);
+		self
+	}
+
+	/// Limits how long scripts may run, measured from the start of each call made from Rust.
+	pub fn timeout(mut self, timeout: Duration) -> Limits {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// Checks whether any limit is set.
+	pub fn is_none(&self) -> bool {
+		self.max_native_calls.is_none() && self.timeout.is_none()
+	}
+}
+
+/// Tracks the work done against a set of limits.
+#[derive(Debug)]
+pub struct Budget {
+	limits: Limits,
+	calls: u64,
+	started: Instant
+}
+
+impl Budget {
+	/// Starts tracking, with the clock starting now.
+	pub fn new(limits: Limits) -> Budget {
+		Budget {
+			limits: limits,
+			calls: 0,
+			started: Instant::now()
+		}
+	}
+
+	/// Restarts the time limit from now.
+	pub fn restart_clock(&mut self) {
+		self.started = Instant::now();
+	}
+
+	/// Records a native call, returning an error message if a limit has been exceeded.
+	pub fn charge(&mut self) -> Result<(), String> {
+		self.calls += 1;
+		if let Some(max) = self.limits.max_native_calls {
+			if self.calls > max {
+				return Err(format!("native call limit of {} exceeded", max));
+			}
+		}
+		if let Some(timeout) = self.limits.timeout {
+			if self.started.elapsed() > timeout {
+				return Err(format!("time limit of {:?} exceeded", timeout));
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Budget, Limits};
+	use std::thread;
+	use std::time::Duration;
+
+	#[test]
+	fn no_limits() {
+		assert!(Limits::none().is_none());
+		assert!(!Limits::none().max_native_calls(1).is_none());
+		let mut budget = Budget::new(Limits::none());
+		for _ in 0..1000 {
+			assert!(budget.charge().is_ok());
+		}
+	}
+
+	#[test]
+	fn native_call_limit() {
+		let mut budget = Budget::new(Limits::none().max_native_calls(2));
+		assert!(budget.charge().is_ok());
+		assert!(budget.charge().is_ok());
+		assert_eq!(budget.charge(), Err("native call limit of 2 exceeded".to_string()));
+		// Restarting the clock does not restart the count
+		budget.restart_clock();
+		assert!(budget.charge().is_err());
+	}
+
+	#[test]
+	fn time_limit() {
+		let mut budget = Budget::new(Limits::none().timeout(Duration::from_millis(20)));
+		assert!(budget.charge().is_ok());
+		thread::sleep(Duration::from_millis(40));
+		assert!(budget.charge().is_err());
+		budget.restart_clock();
+		assert!(budget.charge().is_ok());
+	}
+}
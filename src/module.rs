@@ -0,0 +1,121 @@
+//! Reusable script libraries implemented in Rust.
+
+use std::io::Write;
+
+use {SquirrelVM, SquirrelError};
+
+/// The registry table slot holding the names and versions of installed modules.
+const INSTALLED_KEY: &'static str = "squirrel.installed";
+
+/// A library of functions, classes or values that can be installed into a virtual machine.
+///
+/// This is the way for crates to offer script libraries, in the same way as the standard library's `register_*_lib` methods.
+/// Like `ModuleResolver`, the trait is generic over the streams of the virtual machine rather than its method,
+/// so modules can be boxed as `Box<dyn SquirrelModule<P, E>>`. Most modules work with any streams.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// # use std::io::Write;
+/// struct Greeter;
+///
+/// impl<P: Write + Sync, E: Write + Sync> SquirrelModule<P, E> for Greeter {
+/// 	fn name(&self) -> &str { "greeter" }
+/// 	fn version(&self) -> &str { "1.0.0" }
+///
+/// 	fn register(&self, vm: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError> {
+/// 		vm.push_root_table();
+/// 		vm.push_value("greet");
+/// 		vm.new_closure(|vm| -> Result<String, SquirrelError> {
+/// 			let name: String = try!(vm.read(2));
+/// 			Ok(format!("hello {}", name))
+/// 		}, 0);
+/// 		vm.new_slot(-3, false).map_err(|_| SquirrelError::Runtime("could not add 'greet'".to_string()))
+/// 	}
+/// }
+///
+/// vm.install(Greeter).unwrap();
+/// ```
+pub trait SquirrelModule<P: Write + Sync, E: Write + Sync> {
+	/// The name of the module, which must be unique within a virtual machine.
+	fn name(&self) -> &str;
+
+	/// The version of the module.
+	fn version(&self) -> &str;
+
+	/// Adds the module to `vm`, usually by creating slots in the root table.
+	///
+	/// The stack is restored afterwards, so values can be left on it.
+	fn register(&self, vm: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError>;
+}
+
+impl<P: Write + Sync, E: Write + Sync, M: SquirrelModule<P, E> + ?Sized> SquirrelModule<P, E> for Box<M> {
+	fn name(&self) -> &str {
+		(**self).name()
+	}
+
+	fn version(&self) -> &str {
+		(**self).version()
+	}
+
+	fn register(&self, vm: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError> {
+		(**self).register(vm)
+	}
+}
+
+/// The name and version of an installed module.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ModuleInfo {
+	/// The name of the module.
+	pub name: String,
+	/// The version of the module.
+	pub version: String
+}
+
+/// Installs `module` into `vm`, failing if a module with the same name is already installed.
+pub fn install<M, P, E>(vm: &mut SquirrelVM<P, E>, module: &M) -> Result<(), SquirrelError>
+	where M: SquirrelModule<P, E> + ?Sized, P: Write + Sync, E: Write + Sync
+{
+	let name = module.name();
+	{
+		let mut vm = vm.guard();
+		vm.push_registry_entry(INSTALLED_KEY);
+		vm.push_value(name);
+		if vm.raw_get(-2).is_ok() {
+			return Err(SquirrelError::AlreadyInstalled {
+				name: name.to_string(),
+				version: vm.read(-1).unwrap_or_default()
+			});
+		}
+		// A miss leaves an error behind, which must not be reported by a later failure
+		vm.reset_error();
+	}
+
+	try!(module.register(&mut *vm.guard()));
+
+	let mut vm = vm.guard();
+	vm.push_registry_entry(INSTALLED_KEY);
+	vm.push_value(name);
+	vm.push_value(module.version());
+	vm.new_slot(-3, false).map_err(|_| SquirrelError::Runtime(format!("could not record module '{}'", name)))
+}
+
+/// Lists the installed modules, sorted by name.
+pub fn installed<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>) -> Vec<ModuleInfo> {
+	let mut vm = vm.guard();
+	vm.push_registry_entry(INSTALLED_KEY);
+	let mut modules = Vec::new();
+	vm.push_null();
+	while vm.next(-2).is_ok() {
+		if let (Ok(name), Ok(version)) = (vm.read(-2), vm.read(-1)) {
+			modules.push(ModuleInfo {
+				name: name,
+				version: version
+			});
+		}
+		vm.pop(2);
+	}
+	modules.sort();
+	modules
+}
@@ -0,0 +1,155 @@
+//! Support for native closures implemented in Rust.
+
+use ffi;
+use std::any::Any;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use {SquirrelVM, ToSquirrel};
+
+/// A value that can be returned from a native closure.
+pub trait NativeReturn {
+	/// Pushes the return value (if any) and returns the value expected by Squirrel from a native function.
+	fn push_return<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger;
+}
+
+/// An error that can be thrown into a script from a native closure.
+///
+/// Any `Display` type is thrown as a string, use `Thrown` to throw an arbitrary object instead.
+pub trait ThrowError {
+	/// Throws the error and returns `SQ_ERROR`.
+	fn throw<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger;
+}
+
+/// Wraps a value so that it is thrown as a Squirrel object rather than as a string.
+///
+/// This allows scripts to inspect a structured error in a `catch` block.
+#[derive(Debug, Clone)]
+pub struct Thrown<T>(pub T);
+
+/// A raw return value for native closures that manage the stack themselves.
+///
+/// `RawReturn(1)` returns the value at the top of the stack, `RawReturn(0)` returns nothing,
+/// and `RawReturn(SQ_ERROR)` rethrows the last error, such as one raised by a failed `call`.
+#[derive(Debug, Clone, Copy)]
+pub struct RawReturn(pub ffi::SQInteger);
+
+impl NativeReturn for RawReturn {
+	fn push_return<P: Write + Sync, E: Write + Sync>(self, _: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		self.0
+	}
+}
+
+impl NativeReturn for () {
+	fn push_return<P: Write + Sync, E: Write + Sync>(self, _: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		0
+	}
+}
+
+impl<T: ToSquirrel> NativeReturn for T {
+	fn push_return<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		self.push_to(vm);
+		1
+	}
+}
+
+impl<T: NativeReturn, Err: ThrowError> NativeReturn for Result<T, Err> {
+	fn push_return<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		match self {
+			Ok(t) => t.push_return(vm),
+			Err(e) => e.throw(vm)
+		}
+	}
+}
+
+impl<T: Display> ThrowError for T {
+	fn throw<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		// Interior nulls would truncate the message anyway
+		let msg = self.to_string().replace('\0', "");
+		let msg = CString::new(msg).unwrap();
+		unsafe { ffi::sq_throwerror(vm.0, msg.as_ptr()) }
+	}
+}
+
+impl<T: ToSquirrel> ThrowError for Thrown<T> {
+	fn throw<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) -> ffi::SQInteger {
+		self.0.push_to(vm);
+		unsafe { ffi::sq_throwobject(vm.0) }
+	}
+}
+
+/// Calls the Rust closure stored in the last free variable of the running native closure, charging it to the limits.
+pub extern fn call_native<P: Write + Sync, E: Write + Sync, F, R>(v: ffi::HSQUIRRELVM) -> ffi::SQInteger
+	where F: Fn(&mut SquirrelVM<P, E>) -> R, R: NativeReturn
+{
+	invoke::<P, E, F, R>(v, true)
+}
+
+/// Calls a closure the crate uses internally, such as an error handler or a replaced library function.
+///
+/// These are not charged to the limits, so scripts get the budget they were given.
+pub extern fn call_internal<P: Write + Sync, E: Write + Sync, F, R>(v: ffi::HSQUIRRELVM) -> ffi::SQInteger
+	where F: Fn(&mut SquirrelVM<P, E>) -> R, R: NativeReturn
+{
+	invoke::<P, E, F, R>(v, false)
+}
+
+/// Calls the Rust closure stored in the last free variable, charging the call to the limits if `charge` is set.
+fn invoke<P: Write + Sync, E: Write + Sync, F, R>(v: ffi::HSQUIRRELVM, charge: bool) -> ffi::SQInteger
+	where F: Fn(&mut SquirrelVM<P, E>) -> R, R: NativeReturn
+{
+	let mut vm = ManuallyDrop::new(SquirrelVM(v, PhantomData));
+
+	let func: &F = unsafe {
+		let mut p = ptr::null_mut();
+		ffi::sq_getuserdata(v, -1, &mut p, ptr::null_mut());
+		&**(p as *const *const F)
+	};
+	// Hide our free variable so the closure only sees its own arguments
+	vm.pop_top();
+
+	if let Some(data) = unsafe { ::foreign_data(v) }.filter(|_| charge) {
+		if let Some(Err(msg)) = data.budget.as_mut().map(|budget| budget.charge()) {
+			return msg.throw(&mut vm);
+		}
+	}
+
+	// Unwinding into Squirrel is undefined behaviour, so panics become runtime errors
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		let ret = func(&mut vm);
+		ret.push_return(&mut vm)
+	}));
+	match result {
+		Ok(n) => n,
+		Err(p) => format!("panic in native function: {}", panic_message(&*p)).throw(&mut vm)
+	}
+}
+
+/// Frees the boxed closure owned by a native closure's userdata.
+pub extern fn release_native<F>(p: ffi::SQUserPointer, _: ffi::SQInteger) -> ffi::SQInteger {
+	// There is nowhere to report a panic raised while dropping the closure
+	let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+		unsafe {
+			let _: Box<F> = Box::from_raw(*(p as *mut *mut F));
+		}
+	}));
+	1
+}
+
+/// Gets the message a panic was raised with.
+pub fn panic_message(p: &(dyn Any + Send)) -> String {
+	if let Some(s) = p.downcast_ref::<&str>() {
+		s.to_string()
+	}
+	else if let Some(s) = p.downcast_ref::<String>() {
+		s.clone()
+	}
+	else {
+		"unknown panic".to_string()
+	}
+}
@@ -0,0 +1,380 @@
+//! A `require` function for loading script modules.
+//!
+//! Squirrel has no import statement, so `SquirrelVM::set_module_resolver` adds a `require(name)` function
+//! to the root table. Names are looked up with a `ModuleResolver`, and the exports of each module are cached
+//! in the registry table so that every module is only run once per virtual machine.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use ffi;
+use vfs;
+use {SquirrelVM, SquirrelError, Value, RawReturn, ThrowError};
+
+/// The registry table slot holding the table of loaded modules.
+const CACHE_KEY: &'static str = "squirrel.modules";
+
+/// A function that pushes the exports of a native module.
+pub type NativeLoader<P, E> = Rc<dyn Fn(&mut SquirrelVM<P, E>) -> Result<(), SquirrelError>>;
+
+/// A module found by a `ModuleResolver`.
+pub enum Module<P, E> {
+	/// A script, either source code or compiled bytecode.
+	///
+	/// The script is run with a new table as `this`. Its exports are its return value,
+	/// or that table if it does not return anything.
+	Script {
+		/// The source name used in error messages and call stacks.
+		source: String,
+		/// The contents of the script.
+		data: Vec<u8>
+	},
+	/// A module implemented in Rust, which pushes its exports.
+	Native(NativeLoader<P, E>)
+}
+
+/// Finds the modules loaded by `require`.
+pub trait ModuleResolver<P, E> {
+	/// Finds the module called `name`, returning `Ok(None)` if this resolver does not have it.
+	///
+	/// `require` normalizes names before resolving them, so `./a`, `a` and `a.nut` are all resolved as `a`.
+	fn resolve(&self, name: &str) -> Result<Option<Module<P, E>>, SquirrelError>;
+}
+
+/// Tries each resolver in turn, using the first module found.
+impl<P, E> ModuleResolver<P, E> for Vec<Box<dyn ModuleResolver<P, E>>> {
+	fn resolve(&self, name: &str) -> Result<Option<Module<P, E>>, SquirrelError> {
+		for resolver in self {
+			if let Some(module) = try!(resolver.resolve(name)) {
+				return Ok(Some(module));
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Finds modules in directories on disk.
+///
+/// `require("a/b")` looks for `a/b.nut`, `a/b.cnut` and then `a/b/init.nut` in each search path in turn.
+/// Names cannot refer to files outside the search paths.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// vm.set_module_resolver(FsResolver::new().path("scripts").path("mods"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FsResolver {
+	paths: Vec<PathBuf>
+}
+
+impl FsResolver {
+	/// Creates a resolver with no search paths.
+	pub fn new() -> FsResolver {
+		FsResolver::default()
+	}
+
+	/// Adds a directory to search, after those already added.
+	pub fn path<Q: Into<PathBuf>>(mut self, dir: Q) -> FsResolver {
+		self.paths.push(dir.into());
+		self
+	}
+}
+
+impl<P, E> ModuleResolver<P, E> for FsResolver {
+	fn resolve(&self, name: &str) -> Result<Option<Module<P, E>>, SquirrelError> {
+		let name = try!(module_key(name));
+		let candidates = [format!("{}.nut", name), format!("{}.cnut", name), format!("{}/init.nut", name)];
+
+		for dir in &self.paths {
+			for candidate in candidates.iter() {
+				let path = dir.join(candidate);
+				if !path.is_file() {
+					continue;
+				}
+				let mut data = Vec::new();
+				try!(File::open(&path).and_then(|mut f| f.read_to_end(&mut data)).map_err(|e| SquirrelError::Io {
+					path: path.clone(),
+					desc: e.to_string()
+				}));
+				return Ok(Some(Module::Script {
+					source: path.to_string_lossy().into_owned(),
+					data: data
+				}));
+			}
+		}
+		Ok(None)
+	}
+}
+
+/// Finds modules in a map of scripts held in memory, such as those embedded with `include_bytes!`.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// let mut modules = EmbeddedResolver::new();
+/// modules.insert("util", &b"return { twice = @(x) x * 2 };"[..]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddedResolver {
+	modules: HashMap<String, Cow<'static, [u8]>>
+}
+
+impl EmbeddedResolver {
+	/// Creates an empty map.
+	pub fn new() -> EmbeddedResolver {
+		EmbeddedResolver::default()
+	}
+
+	/// Adds or replaces the module called `name`, which may be source code or compiled bytecode.
+	pub fn insert<D: Into<Cow<'static, [u8]>>>(&mut self, name: &str, data: D) {
+		self.modules.insert(stored_key(name), data.into());
+	}
+}
+
+impl<P, E> ModuleResolver<P, E> for EmbeddedResolver {
+	fn resolve(&self, name: &str) -> Result<Option<Module<P, E>>, SquirrelError> {
+		let key = stored_key(name);
+		Ok(self.modules.get(&key).map(|data| Module::Script {
+			source: key.clone(),
+			data: data.to_vec()
+		}))
+	}
+}
+
+/// Finds modules implemented in Rust.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// let mut modules = NativeResolver::new();
+/// modules.insert("answer", |vm| {
+/// 	vm.push_value(42);
+/// 	Ok(())
+/// });
+/// # vm.set_module_resolver(modules).unwrap();
+/// ```
+pub struct NativeResolver<P, E> {
+	modules: HashMap<String, NativeLoader<P, E>>
+}
+
+impl<P, E> NativeResolver<P, E> {
+	/// Creates an empty resolver.
+	pub fn new() -> NativeResolver<P, E> {
+		NativeResolver {
+			modules: HashMap::new()
+		}
+	}
+
+	/// Adds or replaces the module called `name`.
+	///
+	/// `loader` is called the first time the module is required, and must push the module's exports.
+	pub fn insert<F>(&mut self, name: &str, loader: F)
+		where F: Fn(&mut SquirrelVM<P, E>) -> Result<(), SquirrelError> + 'static
+	{
+		self.modules.insert(stored_key(name), Rc::new(loader));
+	}
+}
+
+impl<P, E> Default for NativeResolver<P, E> {
+	fn default() -> NativeResolver<P, E> {
+		NativeResolver::new()
+	}
+}
+
+impl<P, E> ModuleResolver<P, E> for NativeResolver<P, E> {
+	fn resolve(&self, name: &str) -> Result<Option<Module<P, E>>, SquirrelError> {
+		Ok(self.modules.get(&stored_key(name)).map(|loader| Module::Native(loader.clone())))
+	}
+}
+
+/// Checks that a module name cannot leave a search path.
+fn normalize_name(name: &str) -> Result<String, SquirrelError> {
+	match vfs::normalize(name) {
+		Ok(ref normalized) if !normalized.is_empty() && !name.starts_with('/') => Ok(normalized.clone()),
+		_ => Err(SquirrelError::Runtime(format!("invalid module name '{}'", name)))
+	}
+}
+
+/// Gets the name a module is cached under, so that `./a`, `a` and `a.nut` refer to the same module.
+fn module_key(name: &str) -> Result<String, SquirrelError> {
+	let normalized = try!(normalize_name(name));
+	for ext in [".nut", ".cnut"].iter() {
+		if let Some(stem) = normalized.strip_suffix(ext) {
+			if !stem.is_empty() && !stem.ends_with('/') {
+				return Ok(stem.to_string());
+			}
+		}
+	}
+	Ok(normalized)
+}
+
+/// Gets the key a module added to a map is stored under, keeping names that `require` would reject as they are.
+fn stored_key(name: &str) -> String {
+	module_key(name).unwrap_or_else(|_| name.to_string())
+}
+
+/// Adds `require` to the root table.
+pub fn install<P, E>(vm: &mut SquirrelVM<P, E>, resolver: Rc<dyn ModuleResolver<P, E>>) -> Result<(), SquirrelError>
+	where P: Write + Sync + 'static, E: Write + Sync + 'static
+{
+	// The names of the modules being loaded, outermost first
+	let loading = Rc::new(RefCell::new(Vec::<String>::new()));
+
+	let mut vm = vm.guard();
+	vm.push_root_table();
+	vm.push_value("require");
+	vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+		let name = match vm.read::<String>(2).and_then(|name| module_key(&name)) {
+			Ok(name) => name,
+			Err(e) => return RawReturn(e.throw(vm))
+		};
+
+		vm.push_registry_entry(CACHE_KEY);
+		let cache = vm.get_top();
+		vm.push_value(&name[..]);
+		if vm.raw_get(cache).is_ok() {
+			return RawReturn(1);
+		}
+		// A miss leaves an error behind, which must not be reported by a later failure
+		vm.reset_error();
+
+		if loading.borrow().contains(&name) {
+			let chain = loading.borrow().join(" -> ");
+			return RawReturn(format!("cyclic import: {} -> {}", chain, name).throw(vm));
+		}
+		let module = match resolver.resolve(&name) {
+			Ok(Some(module)) => module,
+			Ok(None) => return RawReturn(format!("module '{}' not found", name).throw(vm)),
+			Err(e) => return RawReturn(e.throw(vm))
+		};
+
+		// The borrow must not be held while the module runs, since it may require other modules
+		loading.borrow_mut().push(name.clone());
+		let result = {
+			let _loading = Loading(&loading);
+			run(vm, module)
+		};
+		match result {
+			Ok(true) => {},
+			Ok(false) => return RawReturn(ffi::SQ_ERROR),
+			Err(e) => return RawReturn(e.throw(vm))
+		}
+
+		vm.push_value(&name[..]);
+		vm.push(-2);
+		match vm.new_slot(cache, false) {
+			Ok(()) => RawReturn(1),
+			Err(()) => RawReturn(ffi::SQ_ERROR)
+		}
+	}, 0);
+	vm.new_slot(-3, false).map_err(|_| SquirrelError::Runtime("could not add 'require'".to_string()))
+}
+
+/// Removes the innermost module from the loading stack when dropped, even if loading it panics.
+struct Loading<'a>(&'a RefCell<Vec<String>>);
+
+impl<'a> Drop for Loading<'a> {
+	fn drop(&mut self) {
+		self.0.borrow_mut().pop();
+	}
+}
+
+/// Runs a module, pushing its exports.
+///
+/// Returns `Ok(false)` if the module threw an error, which is left as the last error.
+fn run<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, module: Module<P, E>) -> Result<bool, SquirrelError> {
+	match module {
+		Module::Script { source, data } => {
+			vm.new_table();
+			let env = vm.get_top();
+			try!(vm.load_bytes(&data, &source));
+			vm.push(env);
+			if vm.call(1, true, false).is_err() {
+				return Ok(false);
+			}
+			if let Ok(Value::Null) = vm.read(-1) {
+				vm.pop(1);
+				vm.push(env);
+			}
+			// Leave only the exports above the environment
+			vm.remove(-2);
+			vm.remove(-2);
+			Ok(true)
+		},
+		Module::Native(loader) => {
+			let top = vm.get_top();
+			try!(loader(vm));
+			if vm.get_top() != top + 1 {
+				vm.set_top(top);
+				return Err(SquirrelError::Runtime("a native module must push exactly one value".to_string()));
+			}
+			Ok(true)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ModuleResolver, Module, EmbeddedResolver, FsResolver, module_key};
+	use std::env;
+	use std::fs;
+
+	type Resolved = Option<Module<Vec<u8>, Vec<u8>>>;
+
+	fn source(module: Resolved) -> Option<String> {
+		match module {
+			Some(Module::Script { source, .. }) => Some(source),
+			_ => None
+		}
+	}
+
+	#[test]
+	fn keys() {
+		assert_eq!(module_key("a").unwrap(), "a");
+		assert_eq!(module_key("./a").unwrap(), "a");
+		assert_eq!(module_key("a.nut").unwrap(), "a");
+		assert_eq!(module_key("lib/../b/c.cnut").unwrap(), "b/c");
+		assert_eq!(module_key(".nut").unwrap(), ".nut");
+		assert!(module_key("/etc/passwd").is_err());
+		assert!(module_key("../a").is_err());
+		assert!(module_key("").is_err());
+	}
+
+	#[test]
+	fn embedded_names() {
+		let mut embedded = EmbeddedResolver::new();
+		embedded.insert("util.nut", &b"return 1"[..]);
+		let found: Resolved = embedded.resolve("./util").unwrap();
+		assert_eq!(source(found), Some("util".to_string()));
+		let missing: Resolved = embedded.resolve("other").unwrap();
+		assert!(missing.is_none());
+	}
+
+	#[test]
+	fn fs_candidates() {
+		let root = env::temp_dir().join(format!("squirrel-require-{}", ::std::process::id()));
+		fs::create_dir_all(root.join("second/pkg")).unwrap();
+		fs::write(root.join("second/pkg/init.nut"), "").unwrap();
+		fs::write(root.join("second/a.nut"), "").unwrap();
+		fs::create_dir_all(root.join("first")).unwrap();
+		fs::write(root.join("first/a.cnut"), "").unwrap();
+		let resolver = FsResolver::new().path(root.join("first")).path(root.join("second"));
+
+		let a: Resolved = resolver.resolve("a.nut").unwrap();
+		assert!(source(a).unwrap().ends_with("a.cnut"));
+		let pkg: Resolved = resolver.resolve("pkg").unwrap();
+		assert!(source(pkg).unwrap().ends_with("init.nut"));
+		let missing: Resolved = resolver.resolve("b").unwrap();
+		assert!(missing.is_none());
+		assert!(ModuleResolver::<Vec<u8>, Vec<u8>>::resolve(&resolver, "../first/a").is_err());
+
+		fs::remove_dir_all(&root).unwrap();
+	}
+}
@@ -0,0 +1,180 @@
+//! Regular expressions using the standard library's regex engine.
+//!
+//! This is the same engine that backs the `regexp` class available to scripts,
+//! so patterns behave identically in Rust and in Squirrel.
+
+use ffi;
+use ffi::stdstring::{SQRex, SQRexMatch};
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::ops::Range;
+use std::ptr;
+
+/// A compiled regular expression.
+pub struct Regex {
+	exp: *mut SQRex,
+	pattern: String
+}
+
+impl Regex {
+	/// Compiles a regular expression.
+	/// # Example
+	/// ```
+	/// # use squirrel::rex::Regex;
+	/// let re = Regex::new("(\\w+)@(\\w+)").unwrap();
+	/// assert_eq!(re.find("mail: bob@example"), Some(6..17));
+	/// ```
+	pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+		let c_pattern = match CString::new(pattern) {
+			Ok(p) => p,
+			Err(_) => return Err(RegexError {
+				pattern: pattern.to_string(),
+				desc: "pattern contains a null character".to_string()
+			})
+		};
+
+		let mut error = ptr::null();
+		let exp = unsafe { ffi::stdstring::sqstd_rex_compile(c_pattern.as_ptr(), &mut error) };
+		if exp.is_null() {
+			let desc = if error.is_null() {
+				"invalid pattern".to_string()
+			}
+			else {
+				unsafe { String::from_utf8_lossy(CStr::from_ptr(error).to_bytes()).into_owned() }
+			};
+			return Err(RegexError {
+				pattern: pattern.to_string(),
+				desc: desc
+			});
+		}
+
+		Ok(Regex {
+			exp: exp,
+			pattern: pattern.to_string()
+		})
+	}
+
+	/// Gets the pattern the expression was compiled from.
+	pub fn as_str(&self) -> &str {
+		&self.pattern[..]
+	}
+
+	/// Returns whether the whole of `text` matches the expression.
+	///
+	/// Like `regexp.match` in scripts, this is an exact match rather than a search.
+	/// The engine takes a null character as the end of the text, so text containing one is an error.
+	pub fn is_match(&self, text: &str) -> Result<bool, RegexError> {
+		match CString::new(text) {
+			Ok(text) => Ok(unsafe { ffi::stdstring::sqstd_rex_match(self.exp, text.as_ptr()) != 0 }),
+			Err(_) => Err(RegexError {
+				pattern: self.pattern.clone(),
+				desc: "text contains a null character".to_string()
+			})
+		}
+	}
+
+	/// Finds the first match of the expression in `text`, returning its byte range.
+	pub fn find(&self, text: &str) -> Option<Range<usize>> {
+		let base = text.as_ptr() as *const ffi::SQChar;
+		let mut begin = ptr::null();
+		let mut end = ptr::null();
+
+		let found = unsafe {
+			ffi::stdstring::sqstd_rex_searchrange(self.exp, base, base.add(text.len()), &mut begin, &mut end)
+		};
+		if found == 0 {
+			return None;
+		}
+
+		Some(offset(base, begin)..offset(base, end))
+	}
+
+	/// Finds the first match of the expression in `text`, returning the byte ranges of its sub-expressions.
+	///
+	/// The first element is the whole match, followed by one element for each group in the pattern.
+	/// Groups that did not take part in the match are `None`.
+	pub fn captures(&self, text: &str) -> Option<Vec<Option<Range<usize>>>> {
+		let whole = self.find(text)?;
+
+		let base = text.as_ptr() as *const ffi::SQChar;
+		let count = unsafe { ffi::stdstring::sqstd_rex_getsubexpcount(self.exp) };
+		let mut captures = vec![Some(whole)];
+		for n in 1..count {
+			let mut m = SQRexMatch {
+				begin: ptr::null(),
+				len: 0
+			};
+			let ok = unsafe { ffi::stdstring::sqstd_rex_getsubexp(self.exp, n, &mut m) != 0 };
+
+			let start = m.begin as usize;
+			let in_text = start >= base as usize && start + m.len as usize <= base as usize + text.len();
+			if ok && !m.begin.is_null() && in_text {
+				let start = offset(base, m.begin);
+				captures.push(Some(start..start + m.len as usize));
+			}
+			else {
+				captures.push(None);
+			}
+		}
+		Some(captures)
+	}
+}
+
+impl Drop for Regex {
+	fn drop(&mut self) {
+		unsafe { ffi::stdstring::sqstd_rex_free(self.exp) }
+	}
+}
+
+impl fmt::Debug for Regex {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Regex({:?})", self.pattern)
+	}
+}
+
+/// Gets the byte offset of `p` from `base`.
+fn offset(base: *const ffi::SQChar, p: *const ffi::SQChar) -> usize {
+	p as usize - base as usize
+}
+
+/// Represents an error compiling a regular expression.
+#[derive(Debug, Clone)]
+pub struct RegexError {
+	/// The pattern that failed to compile.
+	pub pattern: String,
+	/// A description of the error.
+	pub desc: String
+}
+
+impl fmt::Display for RegexError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Error: {} in regular expression '{}'", self.desc, self.pattern)
+	}
+}
+
+impl Error for RegexError {
+	fn description(&self) -> &str {
+		&self.desc[..]
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Regex, offset};
+
+	#[test]
+	fn null_in_pattern() {
+		let e = Regex::new("a\0b").unwrap_err();
+		assert_eq!(e.pattern, "a\0b");
+		assert_eq!(e.to_string(), "Error: pattern contains a null character in regular expression 'a\0b'");
+	}
+
+	#[test]
+	fn byte_offsets() {
+		let text = "bob@example";
+		let base = text.as_ptr() as *const _;
+		assert_eq!(offset(base, base), 0);
+		assert_eq!(offset(base, unsafe { base.offset(3) }), 3);
+	}
+}
@@ -0,0 +1,349 @@
+//! Selective registration of the standard library.
+
+use ffi;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use {SquirrelVM, SquirrelError, RawReturn, ThrowError};
+
+/// Selects which standard library modules are registered and which of their functions scripts may use.
+///
+/// Modules are registered into the root table, after which individual functions are removed or wrapped.
+/// # Example
+/// ```no_run
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// // Scripts may only read files in the mods directory and cannot run commands
+/// let libs = StdLibs::all()
+/// 	.read_only_io()
+/// 	.allow_dir("mods")
+/// 	.without("system")
+/// 	.without("getenv");
+/// libs.register(&mut vm).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct StdLibs {
+	blob: bool,
+	io: bool,
+	math: bool,
+	string: bool,
+	system: bool,
+	read_only_io: bool,
+	allowed_dirs: Option<Vec<PathBuf>>,
+	removed: Vec<String>
+}
+
+/// The functions of the `stdio` lib that take a path as their first argument, besides the `file` class.
+const PATH_FUNCTIONS: [&'static str; 3] = ["loadfile", "dofile", "writeclosuretofile"];
+
+/// The registry slot the `stdio` lib keeps the `file` class in.
+const FILE_CLASS: &'static str = "std_file";
+
+impl StdLibs {
+	/// Selects no modules.
+	pub fn none() -> StdLibs {
+		StdLibs {
+			blob: false,
+			io: false,
+			math: false,
+			string: false,
+			system: false,
+			read_only_io: false,
+			allowed_dirs: None,
+			removed: Vec::new()
+		}
+	}
+
+	/// Selects every module, without restrictions.
+	pub fn all() -> StdLibs {
+		StdLibs {
+			blob: true,
+			io: true,
+			math: true,
+			string: true,
+			system: true,
+			.. StdLibs::none()
+		}
+	}
+
+	/// Selects the modules that are safe for untrusted scripts.
+	///
+	/// This includes the `blob`, `math` and `string` libs, and the `system` lib without
+	/// `system`, `remove`, `rename` and `getenv`. File access can be added with `io` and `allow_dir`.
+	pub fn sandboxed() -> StdLibs {
+		StdLibs::all()
+			.io(false)
+			.without("system")
+			.without("remove")
+			.without("rename")
+			.without("getenv")
+	}
+
+	/// Selects whether the `stdblob` lib is registered.
+	pub fn blob(mut self, enable: bool) -> StdLibs {
+		self.blob = enable;
+		self
+	}
+
+	/// Selects whether the `stdio` lib is registered.
+	pub fn io(mut self, enable: bool) -> StdLibs {
+		self.io = enable;
+		self
+	}
+
+	/// Selects whether the `stdmath` lib is registered.
+	pub fn math(mut self, enable: bool) -> StdLibs {
+		self.math = enable;
+		self
+	}
+
+	/// Selects whether the `stdstring` lib is registered.
+	pub fn string(mut self, enable: bool) -> StdLibs {
+		self.string = enable;
+		self
+	}
+
+	/// Selects whether the `stdsystem` lib is registered.
+	pub fn system(mut self, enable: bool) -> StdLibs {
+		self.system = enable;
+		self
+	}
+
+	/// Prevents scripts from opening files for writing and from writing closures to files.
+	///
+	/// The constructor of the `file` class rejects write modes. The check is made by the class itself, so it also
+	/// applies to the class reached through `stdout.getclass()` or an open file, and to classes that extend it.
+	pub fn read_only_io(mut self) -> StdLibs {
+		self.read_only_io = true;
+		self
+	}
+
+	/// Allows scripts to access files inside `dir`.
+	///
+	/// Once a directory has been allowed, every path passed to `file`, `loadfile`, `dofile`
+	/// and `writeclosuretofile` must resolve to a location inside an allowed directory. Like `read_only_io`,
+	/// this is checked by the constructor of the `file` class itself.
+	///
+	/// Relative paths are resolved against the current directory, and the resolved path is the one that is
+	/// opened, so a symbolic link changed after the check cannot redirect it. Links in directories that
+	/// scripts can write to outside of Squirrel can still be changed between the check and the open.
+	pub fn allow_dir<Q: AsRef<Path>>(mut self, dir: Q) -> StdLibs {
+		self.allowed_dirs.get_or_insert_with(Vec::new).push(dir.as_ref().to_path_buf());
+		self
+	}
+
+	/// Removes a function (or any other value) from the root table after registration.
+	pub fn without(mut self, name: &str) -> StdLibs {
+		self.removed.push(name.to_string());
+		self
+	}
+
+	/// Registers the selected modules into the root table of `vm`.
+	pub fn register<P: Write + Sync, E: Write + Sync>(&self, vm: &mut SquirrelVM<P, E>) -> Result<(), SquirrelError> {
+		let mut vm = vm.guard();
+		vm.push_root_table();
+
+		if self.blob {
+			try!(vm.register_blob_lib().map_err(|_| registration_error("blob")));
+		}
+		if self.io {
+			try!(vm.register_io_lib().map_err(|_| registration_error("io")));
+		}
+		if self.math {
+			try!(vm.register_math_lib().map_err(|_| registration_error("math")));
+		}
+		if self.string {
+			try!(vm.register_string_lib().map_err(|_| registration_error("string")));
+		}
+		if self.system {
+			try!(vm.register_system_lib().map_err(|_| registration_error("system")));
+		}
+
+		for name in &self.removed {
+			vm.push_value(&name[..]);
+			// Removing a missing slot is not an error
+			if vm.delete_slot(-2, false).is_err() {
+				vm.reset_error();
+			}
+		}
+
+		if self.io {
+			if self.read_only_io {
+				vm.push_value("writeclosuretofile");
+				let _ = vm.delete_slot(-2, false);
+			}
+
+			let dirs = match self.allowed_dirs {
+				Some(ref dirs) => Some(Rc::new(try!(canonical_dirs(dirs)))),
+				None => None
+			};
+			if self.read_only_io || dirs.is_some() {
+				// Scripts can reach the class through any file object, so its constructor must make the checks
+				vm.push_registry_table();
+				vm.push_value(FILE_CLASS);
+				if vm.raw_get(-2).is_ok() {
+					try!(wrap_path_function(&mut vm, "constructor", self.read_only_io, dirs.clone()));
+					vm.pop(1);
+				}
+				else {
+					vm.reset_error();
+				}
+				vm.pop(1);
+			}
+			if dirs.is_some() {
+				for name in PATH_FUNCTIONS.iter() {
+					try!(wrap_path_function(&mut vm, name, false, dirs.clone()));
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Default for StdLibs {
+	fn default() -> StdLibs {
+		StdLibs::all()
+	}
+}
+
+fn registration_error(lib: &str) -> SquirrelError {
+	SquirrelError::Runtime(format!("could not register the {} lib", lib))
+}
+
+/// Resolves the allowed directories so that paths can be compared against them.
+fn canonical_dirs(dirs: &[PathBuf]) -> Result<Vec<PathBuf>, SquirrelError> {
+	dirs.iter().map(|dir| {
+		dir.canonicalize().map_err(|e| SquirrelError::Io {
+			path: dir.clone(),
+			desc: e.to_string()
+		})
+	}).collect()
+}
+
+/// Resolves `path`, returning the resolved path if it is inside one of `dirs`.
+fn resolve_allowed(path: &Path, dirs: &[PathBuf]) -> Option<PathBuf> {
+	let full = match env::current_dir() {
+		Ok(cwd) => cwd.join(path),
+		Err(_) => return None
+	};
+	// Files that are about to be created do not exist yet, so resolve their parent instead
+	let resolved = match full.canonicalize() {
+		Ok(p) => p,
+		Err(_) => match (full.parent().and_then(|p| p.canonicalize().ok()), full.file_name()) {
+			(Some(parent), Some(name)) => parent.join(name),
+			_ => return None
+		}
+	};
+	if dirs.iter().any(|dir| resolved.starts_with(dir)) {
+		Some(resolved)
+	}
+	else {
+		None
+	}
+}
+
+/// Replaces the slot `name` with a native closure that checks its arguments before calling the original.
+///
+/// The table or class holding the function must be at the top of the stack. When directories are allowed,
+/// the original is called with the resolved path in place of the one the script passed.
+fn wrap_path_function<P, E>(vm: &mut SquirrelVM<P, E>, name: &'static str, read_only: bool, dirs: Option<Rc<Vec<PathBuf>>>) -> Result<(), SquirrelError>
+	where P: Write + Sync, E: Write + Sync
+{
+	vm.push_value(name);
+	vm.push_value(name);
+	if vm.get(-3).is_err() {
+		// The function was removed
+		vm.reset_error();
+		vm.pop(1);
+		return Ok(());
+	}
+
+	// The original function becomes the closure's free variable, at the top of the stack when called
+	vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+		let top = vm.get_top();
+
+		// Handles to open streams are passed by the host, such as by `set_vfs`, rather than by scripts
+		if top > 2 && unsafe { ffi::sq_gettype(vm.0, 2) } == ffi::SQObjectType::OT_USERPOINTER {
+			return call_original(vm, top, None);
+		}
+		let mut path: String = match vm.read(2) {
+			Ok(path) => path,
+			Err(e) => return RawReturn(e.throw(vm))
+		};
+		if let Some(ref dirs) = dirs {
+			match resolve_allowed(Path::new(&path), dirs).and_then(|p| p.to_str().map(|p| p.to_string())) {
+				Some(resolved) => path = resolved,
+				None => return RawReturn(format!("access to '{}' is not allowed", path).throw(vm))
+			}
+		}
+		if read_only {
+			let mode: String = vm.read(3).unwrap_or_default();
+			if mode.contains(['w', 'a', '+']) {
+				return RawReturn(format!("files cannot be opened for writing (mode '{}')", mode).throw(vm));
+			}
+		}
+
+		call_original(vm, top, Some(&path))
+	}, 1);
+
+	vm.new_slot(-3, false).map_err(|_| SquirrelError::Runtime(format!("could not replace '{}'", name)))
+}
+
+/// Calls the original function, the free variable at `top`, with the same arguments, leaving its return value at the top.
+///
+/// If `path` is given, it replaces the first argument after `this`.
+fn call_original<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, top: isize, path: Option<&str>) -> RawReturn {
+	vm.push(top);
+	for i in 1..top {
+		match path {
+			Some(path) if i == 2 => vm.push_value(path),
+			_ => vm.push(i)
+		}
+	}
+	match vm.call(top - 1, true, false) {
+		Ok(()) => RawReturn(1),
+		Err(()) => RawReturn(ffi::SQ_ERROR)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{StdLibs, canonical_dirs, resolve_allowed};
+	use std::env;
+	use std::fs;
+
+	#[test]
+	fn sandboxed_selection() {
+		let libs = StdLibs::sandboxed();
+		assert!(libs.blob && libs.math && libs.string && libs.system);
+		assert!(!libs.io);
+		assert_eq!(libs.removed, ["system", "remove", "rename", "getenv"]);
+	}
+
+	#[test]
+	fn allowed_paths() {
+		let root = env::temp_dir().join(format!("squirrel-stdlib-{}", ::std::process::id()));
+		let allowed = root.join("allowed");
+		fs::create_dir_all(&allowed).unwrap();
+		fs::write(allowed.join("a.nut"), "").unwrap();
+		let dirs = canonical_dirs(std::slice::from_ref(&allowed)).unwrap();
+
+		let inside = resolve_allowed(&allowed.join("a.nut"), &dirs).unwrap();
+		assert!(inside.starts_with(&dirs[0]));
+		// Files that do not exist yet resolve through their parent
+		assert!(resolve_allowed(&allowed.join("new.nut"), &dirs).is_some());
+		assert!(resolve_allowed(&allowed.join("../a.nut"), &dirs).is_none());
+		assert!(resolve_allowed(&root.join("b.nut"), &dirs).is_none());
+		assert!(resolve_allowed(&allowed.join("missing/c.nut"), &dirs).is_none());
+
+		fs::remove_dir_all(&root).unwrap();
+	}
+
+	#[test]
+	fn missing_allowed_dir() {
+		assert!(canonical_dirs(&[env::temp_dir().join("squirrel-stdlib-missing")]).is_err());
+	}
+}
@@ -0,0 +1,121 @@
+//! Rust streams exposed to scripts as `FILE` handles.
+//!
+//! The standard library's `file` class only works with C `FILE` handles,
+//! so streams are wrapped with `fopencookie`, which calls back into Rust for every operation.
+//! `fopencookie` is only available on Linux, but the `Stream` trait is used everywhere.
+
+#[cfg(target_os = "linux")]
+use libc::{self, c_char, c_int, c_void, size_t, ssize_t};
+use std::io::{Read, Write, Seek};
+#[cfg(target_os = "linux")]
+use std::io::SeekFrom;
+#[cfg(target_os = "linux")]
+use std::panic::{self, AssertUnwindSafe};
+#[cfg(target_os = "linux")]
+use std::slice;
+
+/// A stream that can be used by scripts as a `file`.
+pub trait Stream: Read + Write + Seek {}
+
+impl<T: Read + Write + Seek> Stream for T {}
+
+#[cfg(target_os = "linux")]
+type ReadFn = extern fn(*mut c_void, *mut c_char, size_t) -> ssize_t;
+#[cfg(target_os = "linux")]
+type WriteFn = extern fn(*mut c_void, *const c_char, size_t) -> ssize_t;
+#[cfg(target_os = "linux")]
+type SeekFn = extern fn(*mut c_void, *mut i64, c_int) -> c_int;
+#[cfg(target_os = "linux")]
+type CloseFn = extern fn(*mut c_void) -> c_int;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct CookieIoFunctions {
+	read: Option<ReadFn>,
+	write: Option<WriteFn>,
+	seek: Option<SeekFn>,
+	close: Option<CloseFn>
+}
+
+#[cfg(target_os = "linux")]
+extern {
+	fn fopencookie(cookie: *mut c_void, mode: *const c_char, io_funcs: CookieIoFunctions) -> *mut libc::FILE;
+}
+
+#[cfg(target_os = "linux")]
+/// Opens a `FILE` handle that reads from and writes to `stream`.
+///
+/// The stream is dropped when the handle is closed. Returns a null pointer on failure.
+pub fn open<S: Stream>(stream: S) -> *mut libc::FILE {
+	let cookie = Box::into_raw(Box::new(stream));
+	let funcs = CookieIoFunctions {
+		read: Some(read_fn::<S>),
+		write: Some(write_fn::<S>),
+		seek: Some(seek_fn::<S>),
+		close: Some(close_fn::<S>)
+	};
+
+	unsafe {
+		let file = fopencookie(cookie as *mut c_void, b"r+\0".as_ptr() as *const c_char, funcs);
+		if file.is_null() {
+			let _: Box<S> = Box::from_raw(cookie);
+		}
+		else {
+			// Let the stream see writes as soon as a script makes them
+			libc::setvbuf(file, ::std::ptr::null_mut(), libc::_IONBF, 0);
+		}
+		file
+	}
+}
+
+#[cfg(target_os = "linux")]
+extern fn read_fn<S: Stream>(cookie: *mut c_void, buf: *mut c_char, size: size_t) -> ssize_t {
+	let stream = unsafe { &mut *(cookie as *mut S) };
+	let buf = unsafe { slice::from_raw_parts_mut(buf as *mut u8, size) };
+	match panic::catch_unwind(AssertUnwindSafe(|| stream.read(buf))) {
+		Ok(Ok(n)) => n as ssize_t,
+		_ => -1
+	}
+}
+
+#[cfg(target_os = "linux")]
+extern fn write_fn<S: Stream>(cookie: *mut c_void, buf: *const c_char, size: size_t) -> ssize_t {
+	let stream = unsafe { &mut *(cookie as *mut S) };
+	let buf = unsafe { slice::from_raw_parts(buf as *const u8, size) };
+	// Returning a short count is treated as an error, so write everything
+	match panic::catch_unwind(AssertUnwindSafe(|| stream.write_all(buf).and_then(|_| stream.flush()))) {
+		Ok(Ok(())) => size as ssize_t,
+		_ => -1
+	}
+}
+
+#[cfg(target_os = "linux")]
+extern fn seek_fn<S: Stream>(cookie: *mut c_void, offset: *mut i64, whence: c_int) -> c_int {
+	let stream = unsafe { &mut *(cookie as *mut S) };
+	let off = unsafe { *offset };
+	let pos = match whence {
+		libc::SEEK_SET if off >= 0 => SeekFrom::Start(off as u64),
+		libc::SEEK_CUR => SeekFrom::Current(off),
+		libc::SEEK_END => SeekFrom::End(off),
+		_ => return -1
+	};
+	match panic::catch_unwind(AssertUnwindSafe(|| stream.seek(pos))) {
+		Ok(Ok(n)) => {
+			unsafe { *offset = n as i64; }
+			0
+		},
+		_ => -1
+	}
+}
+
+#[cfg(target_os = "linux")]
+extern fn close_fn<S: Stream>(cookie: *mut c_void) -> c_int {
+	let result = panic::catch_unwind(AssertUnwindSafe(|| {
+		let mut stream: Box<S> = unsafe { Box::from_raw(cookie as *mut S) };
+		stream.flush()
+	}));
+	match result {
+		Ok(Ok(())) => 0,
+		_ => -1
+	}
+}
@@ -0,0 +1,218 @@
+//! A handle based interface to Squirrel tables.
+
+use ffi;
+use std::io::Write;
+use std::vec;
+
+use {SquirrelVM, SquirrelError, FromSquirrel, ToSquirrel, Handle, Value};
+
+/// A table held by a handle, which can be used without managing the stack.
+///
+/// Every method leaves the stack as it found it. Methods come in two forms: the plain ones behave like the
+/// script operators, following the delegate and calling its metamethods, while the `raw_` ones only look at
+/// the table's own slots and never call metamethods.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// vm.new_table();
+/// let mut table = vm.table(-1).unwrap();
+/// table.set("name", "squirrel").unwrap();
+/// assert_eq!(table.get::<_, String>("name").unwrap(), "squirrel");
+/// ```
+pub struct TableRef<'a, P: Write + Sync + 'a, E: Write + Sync + 'a> {
+	vm: &'a mut SquirrelVM<P, E>,
+	handle: Handle
+}
+
+impl<'a, P: Write + Sync, E: Write + Sync> TableRef<'a, P, E> {
+	/// Wraps a handle, which must refer to a table.
+	pub fn new(vm: &'a mut SquirrelVM<P, E>, handle: Handle) -> Result<TableRef<'a, P, E>, SquirrelError> {
+		if handle.object_type() != ffi::SQObjectType::OT_TABLE {
+			return Err(SquirrelError::Runtime("the handle does not refer to a table".to_string()));
+		}
+		Ok(TableRef {
+			vm: vm,
+			handle: handle
+		})
+	}
+
+	/// Gets the handle of the table.
+	pub fn handle(&self) -> &Handle {
+		&self.handle
+	}
+
+	/// Gets the number of slots in the table itself, not counting its delegate.
+	pub fn len(&mut self) -> usize {
+		let size = self.with(|vm| unsafe { ffi::sq_getsize(vm.0, -1) });
+		if size < 0 { 0 } else { size as usize }
+	}
+
+	pub fn is_empty(&mut self) -> bool {
+		self.len() == 0
+	}
+
+	/// Reads the value of a key like `table[key]`.
+	///
+	/// Keys missing from the table are looked up in its delegate, whose `_get` metamethod is called if it has one.
+	pub fn get<K: ToSquirrel, T: FromSquirrel>(&mut self, key: K) -> Result<T, SquirrelError> {
+		self.with(|vm| {
+			let key = push_key(vm, key);
+			match vm.get(-2) {
+				Ok(()) => vm.read(-1),
+				Err(()) => Err(missing(vm, &key))
+			}
+		})
+	}
+
+	/// Reads the value of a key in the table itself, without calling metamethods.
+	pub fn raw_get<K: ToSquirrel, T: FromSquirrel>(&mut self, key: K) -> Result<T, SquirrelError> {
+		self.with(|vm| {
+			let key = push_key(vm, key);
+			match vm.raw_get(-2) {
+				Ok(()) => vm.read(-1),
+				Err(()) => Err(missing(vm, &key))
+			}
+		})
+	}
+
+	/// Sets the value of a key like `table[key] <- value`, creating the slot if needed.
+	///
+	/// When the key is new, the `_newslot` metamethod of the delegate is called if it has one.
+	/// The `_set` metamethod is not called, as it only handles assignments to missing keys with `=`.
+	pub fn set<K: ToSquirrel, V: ToSquirrel>(&mut self, key: K, value: V) -> Result<(), SquirrelError> {
+		self.try_with(|vm| {
+			key.push_to(vm);
+			value.push_to(vm);
+			vm.new_slot(-3, false)
+		})
+	}
+
+	/// Sets the value of a key in the table itself, creating the slot if needed, without calling metamethods.
+	pub fn raw_set<K: ToSquirrel, V: ToSquirrel>(&mut self, key: K, value: V) -> Result<(), SquirrelError> {
+		self.try_with(|vm| {
+			key.push_to(vm);
+			value.push_to(vm);
+			vm.raw_set(-3)
+		})
+	}
+
+	/// Checks whether a key can be read with `get`, following the delegate and calling `_get`.
+	pub fn contains<K: ToSquirrel>(&mut self, key: K) -> bool {
+		self.with(|vm| {
+			key.push_to(vm);
+			let found = vm.get(-2).is_ok();
+			vm.reset_error();
+			found
+		})
+	}
+
+	/// Checks whether the table itself has a slot for a key, without calling metamethods.
+	pub fn raw_contains<K: ToSquirrel>(&mut self, key: K) -> bool {
+		self.with(|vm| {
+			key.push_to(vm);
+			let found = vm.raw_get(-2).is_ok();
+			vm.reset_error();
+			found
+		})
+	}
+
+	/// Removes a slot like `delete table[key]`, returning its value.
+	///
+	/// The `_delslot` metamethod of the delegate is called if it has one.
+	pub fn remove<K: ToSquirrel, T: FromSquirrel>(&mut self, key: K) -> Result<T, SquirrelError> {
+		self.with(|vm| {
+			let key = push_key(vm, key);
+			match vm.delete_slot(-2, true) {
+				Ok(()) => vm.read(-1),
+				Err(()) => Err(missing(vm, &key))
+			}
+		})
+	}
+
+	/// Removes a slot from the table itself, returning its value, without calling metamethods.
+	pub fn raw_remove<K: ToSquirrel, T: FromSquirrel>(&mut self, key: K) -> Result<T, SquirrelError> {
+		self.with(|vm| {
+			let key = push_key(vm, key);
+			match vm.raw_delete_slot(-2, true) {
+				Ok(()) => vm.read(-1),
+				Err(()) => Err(missing(vm, &key))
+			}
+		})
+	}
+
+	/// Removes every slot from the table itself.
+	pub fn clear(&mut self) {
+		let _ = self.try_with(|vm| vm.clear(-1));
+	}
+
+	/// Reads the keys of the table itself, in iteration order.
+	pub fn keys<K: FromSquirrel>(&mut self) -> Result<Vec<K>, SquirrelError> {
+		let mut keys = Vec::new();
+		try!(self.each(|vm| {
+			keys.push(try!(vm.read(-2)));
+			Ok(())
+		}));
+		Ok(keys)
+	}
+
+	/// Reads the slots of the table itself, in iteration order.
+	///
+	/// Slots are read up front, so the table can be changed while the result is iterated. Metamethods are not called.
+	pub fn iter<K: FromSquirrel, V: FromSquirrel>(&mut self) -> Result<vec::IntoIter<(K, V)>, SquirrelError> {
+		let mut slots = Vec::new();
+		try!(self.each(|vm| {
+			slots.push((try!(vm.read(-2)), try!(vm.read(-1))));
+			Ok(())
+		}));
+		Ok(slots.into_iter())
+	}
+
+	/// Calls `f` with each key and value pushed.
+	fn each<F>(&mut self, mut f: F) -> Result<(), SquirrelError>
+		where F: FnMut(&mut SquirrelVM<P, E>) -> Result<(), SquirrelError>
+	{
+		self.with(|vm| {
+			vm.push_null();
+			while vm.next(-2).is_ok() {
+				try!(f(vm));
+				vm.pop(2);
+			}
+			Ok(())
+		})
+	}
+
+	/// Calls `f` with the table pushed, restoring the stack afterwards.
+	fn with<T, F: FnOnce(&mut SquirrelVM<P, E>) -> T>(&mut self, f: F) -> T {
+		let mut vm = self.vm.guard();
+		vm.push_handle(&self.handle);
+		f(&mut vm)
+	}
+
+	/// Like `with`, but turns a failed operation into the error it raised.
+	fn try_with<F: FnOnce(&mut SquirrelVM<P, E>) -> Result<(), ()>>(&mut self, f: F) -> Result<(), SquirrelError> {
+		self.with(|vm| match f(vm) {
+			Ok(()) => Ok(()),
+			Err(()) => Err(SquirrelError::Runtime(vm.take_last_error()))
+		})
+	}
+}
+
+/// Pushes a key, keeping a copy to describe it if it is missing.
+///
+/// Any earlier error is cleared, so that `missing` only reports errors raised by metamethods.
+fn push_key<P: Write + Sync, E: Write + Sync, K: ToSquirrel>(vm: &mut SquirrelVM<P, E>, key: K) -> Value {
+	vm.reset_error();
+	key.push_to(vm);
+	vm.type_of(-1)
+}
+
+/// Builds the error for a key that could not be found, preferring any error raised by a metamethod.
+fn missing<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, key: &Value) -> SquirrelError {
+	let msg = vm.take_last_error();
+	if !msg.is_empty() {
+		return SquirrelError::Runtime(msg);
+	}
+	SquirrelError::Runtime(format!("the index '{}' does not exist", key))
+}
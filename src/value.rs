@@ -0,0 +1,375 @@
+//! A dynamically typed representation of Squirrel values.
+
+use ffi;
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::rc::Rc;
+
+use {SquirrelVM, SquirrelError, FromSquirrel, ToSquirrel};
+
+/// Represents a value read from the stack of a virtual machine.
+///
+/// Each variant mirrors an `SQObjectType`. Reference types hold a `Handle`, which keeps the object alive.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Null,
+	Integer(ffi::SQInteger),
+	Float(ffi::SQFloat),
+	Bool(bool),
+	String(String),
+	Table(Handle),
+	Array(Handle),
+	/// A function written in Squirrel.
+	Closure(Handle),
+	/// A function written in C or Rust.
+	NativeClosure(Handle),
+	Generator(Handle),
+	UserData(Handle),
+	UserPointer(ffi::SQUserPointer),
+	Thread(Handle),
+	Class(Handle),
+	Instance(Handle),
+	WeakRef(Handle)
+}
+
+impl Value {
+	/// Gets the name of the type of the value, as returned by `typeof`.
+	///
+	/// Unlike `typeof`, the `_typeof` metamethod of instances is not called.
+	pub fn type_name(&self) -> &'static str {
+		match *self {
+			Value::Null => "null",
+			Value::Integer(_) => "integer",
+			Value::Float(_) => "float",
+			Value::Bool(_) => "bool",
+			Value::String(_) => "string",
+			Value::Table(_) => "table",
+			Value::Array(_) => "array",
+			Value::Closure(_) | Value::NativeClosure(_) => "function",
+			Value::Generator(_) => "generator",
+			Value::UserData(_) => "userdata",
+			Value::UserPointer(_) => "userpointer",
+			Value::Thread(_) => "thread",
+			Value::Class(_) => "class",
+			Value::Instance(_) => "instance",
+			Value::WeakRef(_) => "weakref"
+		}
+	}
+
+	/// Gets the handle of a reference type.
+	pub fn handle(&self) -> Option<&Handle> {
+		match *self {
+			Value::Table(ref h) | Value::Array(ref h) | Value::Closure(ref h) | Value::NativeClosure(ref h) |
+			Value::Generator(ref h) | Value::UserData(ref h) | Value::Thread(ref h) | Value::Class(ref h) |
+			Value::Instance(ref h) | Value::WeakRef(ref h) => Some(h),
+			_ => None
+		}
+	}
+
+	/// Checks whether the value is an instance of `class`, or of a class derived from it, like `instanceof`.
+	pub fn instance_of(&self, class: &Value) -> bool {
+		match (self, class) {
+			(Value::Instance(instance), Value::Class(class)) => instance.instance_of(class),
+			_ => false
+		}
+	}
+}
+
+impl PartialOrd for Value {
+	/// Compares values like the `<=>` operator, calling the `_cmp` metamethod of instances, tables and userdata.
+	///
+	/// Integers and floats can be compared with each other. As they are never equal, an integer is ordered
+	/// before a float of the same value. Other values of different types are unordered, as are values whose
+	/// `_cmp` metamethod throws or does not return an integer.
+	fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+		match (self, other) {
+			(&Value::Null, &Value::Null) => Some(Ordering::Equal),
+			(&Value::Integer(a), &Value::Integer(b)) => a.partial_cmp(&b),
+			(&Value::Float(a), &Value::Float(b)) => a.partial_cmp(&b),
+			(&Value::Integer(a), &Value::Float(b)) => (a as ffi::SQFloat).partial_cmp(&b).map(|o| o.then(Ordering::Less)),
+			(&Value::Float(a), &Value::Integer(b)) => a.partial_cmp(&(b as ffi::SQFloat)).map(|o| o.then(Ordering::Greater)),
+			(&Value::Bool(a), &Value::Bool(b)) => a.partial_cmp(&b),
+			(Value::String(a), Value::String(b)) => a.partial_cmp(b),
+			(&Value::UserPointer(a), &Value::UserPointer(b)) => a.partial_cmp(&b),
+			_ => match (self.handle(), other.handle()) {
+				(Some(a), Some(b)) if a.object_type() == b.object_type() => a.partial_cmp(b),
+				_ => None
+			}
+		}
+	}
+}
+
+impl Hash for Value {
+	/// Hashes the value consistently with equality: reference types hash by identity through `sq_gethash`.
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.type_name().hash(state);
+		match *self {
+			Value::Null => {},
+			Value::Integer(i) => i.hash(state),
+			// Zero and negative zero are equal, so must hash the same
+			Value::Float(f) => (if f == 0.0 { 0.0 } else { f }).to_bits().hash(state),
+			Value::Bool(b) => b.hash(state),
+			Value::String(ref s) => s.hash(state),
+			Value::UserPointer(p) => (p as usize).hash(state),
+			ref value => if let Some(h) = value.handle() {
+				h.hash(state);
+			}
+		}
+	}
+}
+
+impl fmt::Display for Value {
+	/// Formats the value like `tostring()`, which calls the `_tostring` metamethod of instances and tables.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Value::Null => write!(f, "null"),
+			Value::Integer(i) => write!(f, "{}", i),
+			Value::Float(n) => write!(f, "{}", ::debug::format_float(n as f64, 6)),
+			Value::Bool(b) => write!(f, "{}", b),
+			Value::String(ref s) => write!(f, "{}", s),
+			Value::UserPointer(p) => write!(f, "(userpointer : {:p})", p),
+			ref value => match value.handle().and_then(|h| h.to_string_lossy()) {
+				Some(s) => write!(f, "{}", s),
+				None => write!(f, "({})", value.type_name())
+			}
+		}
+	}
+}
+
+impl<'a> ToSquirrel for &'a Value {
+	/// Pushes the value. Handles whose virtual machine has been closed are pushed as `null`.
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		match *self {
+			Value::Null => vm.push_null(),
+			Value::Integer(i) => vm.push_integer(i),
+			Value::Float(f) => vm.push_float(f),
+			Value::Bool(b) => vm.push_bool(b),
+			Value::String(ref s) => vm.push_value(&s[..]),
+			Value::UserPointer(p) => unsafe { ffi::sq_pushuserpointer(vm.0, p); },
+			ref value => match value.handle() {
+				Some(h) if h.is_alive() => unsafe { ffi::sq_pushobject(vm.0, h.inner.obj); },
+				_ => vm.push_null()
+			}
+		}
+	}
+}
+
+impl ToSquirrel for Value {
+	fn push_to<P: Write + Sync, E: Write + Sync>(self, vm: &mut SquirrelVM<P, E>) {
+		(&self).push_to(vm);
+	}
+}
+
+impl FromSquirrel for Value {
+	fn read<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Result<Value, SquirrelError> {
+		use ffi::SQObjectType::*;
+
+		let t = unsafe { ffi::sq_gettype(vm.0, idx) };
+		let handle = || Handle::new(vm, idx);
+		Ok(match t {
+			OT_NULL => Value::Null,
+			OT_INTEGER => Value::Integer(try!(vm.read(idx))),
+			OT_FLOAT => Value::Float(try!(vm.read(idx))),
+			OT_BOOL => Value::Bool(try!(vm.read(idx))),
+			OT_STRING => Value::String(try!(vm.read(idx))),
+			OT_TABLE => Value::Table(handle()),
+			OT_ARRAY => Value::Array(handle()),
+			OT_CLOSURE => Value::Closure(handle()),
+			OT_NATIVECLOSURE => Value::NativeClosure(handle()),
+			OT_GENERATOR => Value::Generator(handle()),
+			OT_USERDATA => Value::UserData(handle()),
+			OT_USERPOINTER => {
+				let mut p = ::std::ptr::null_mut();
+				unsafe { ffi::sq_getuserpointer(vm.0, idx, &mut p); }
+				Value::UserPointer(p)
+			},
+			OT_THREAD => Value::Thread(handle()),
+			OT_CLASS => Value::Class(handle()),
+			OT_INSTANCE => Value::Instance(handle()),
+			OT_WEAKREF => Value::WeakRef(handle()),
+			// Function prototypes and outers never appear on the stack
+			OT_FUNCPROTO | OT_OUTER => Value::Null
+		})
+	}
+}
+
+/// A strong reference to a Squirrel object, which keeps it from being collected.
+///
+/// Cloning a handle is cheap and refers to the same object. Handles compare equal when they refer to the same object.
+/// A handle can outlive its virtual machine, but can no longer be used once it has been closed.
+/// Handles belong to the root virtual machine rather than the thread they were created on, so they stay
+/// usable for as long as the root is open, even after the thread has been collected.
+#[derive(Clone)]
+pub struct Handle {
+	inner: Rc<HandleInner>
+}
+
+struct HandleInner {
+	/// The root virtual machine, which every thread shares objects with
+	vm: ffi::HSQUIRRELVM,
+	obj: ffi::HSQOBJECT,
+	/// Cleared when the root virtual machine is closed, after which the reference must not be released
+	alive: Rc<Cell<bool>>
+}
+
+impl Handle {
+	/// Creates a handle to the object at `idx`, adding a reference to it.
+	pub fn new<P: Write + Sync, E: Write + Sync>(vm: &SquirrelVM<P, E>, idx: isize) -> Handle {
+		let mut obj = ffi::HSQOBJECT {
+			_type: ffi::SQObjectType::OT_NULL,
+			_unVal: ffi::SQObjectValue { raw: 0 }
+		};
+		// Every machine created by this crate records its root, which lives as long as the objects do
+		let root = unsafe { ::root_state(vm.0) }.expect("the virtual machine was not created by this crate");
+		unsafe {
+			ffi::sq_getstackobj(vm.0, idx, &mut obj);
+			ffi::sq_addref(root.vm, &mut obj);
+		}
+		Handle {
+			inner: Rc::new(HandleInner {
+				vm: root.vm,
+				obj: obj,
+				alive: root.alive.clone()
+			})
+		}
+	}
+
+	/// Gets the type of the object.
+	pub fn object_type(&self) -> ffi::SQObjectType {
+		self.inner.obj._type
+	}
+
+	/// Gets the underlying object, which can be pushed with `sq_pushobject`.
+	pub fn raw(&self) -> ffi::HSQOBJECT {
+		self.inner.obj
+	}
+
+	/// Checks whether the root virtual machine the handle belongs to is still open.
+	pub fn is_alive(&self) -> bool {
+		self.inner.alive.get()
+	}
+
+	/// Checks whether the instance is an instance of `class`, or of a class derived from it.
+	pub fn instance_of(&self, class: &Handle) -> bool {
+		if !self.is_alive() || !class.is_alive() {
+			return false;
+		}
+		let v = self.inner.vm;
+		unsafe {
+			ffi::sq_pushobject(v, self.inner.obj);
+			ffi::sq_pushobject(v, class.inner.obj);
+			let result = ffi::sq_instanceof(v) != 0;
+			ffi::sq_pop(v, 2);
+			result
+		}
+	}
+
+	/// Converts the object to a string like `tostring()`, or returns `None` if the virtual machine has been closed.
+	fn to_string_lossy(&self) -> Option<String> {
+		if !self.is_alive() {
+			return None;
+		}
+		let v = self.inner.vm;
+		unsafe {
+			ffi::sq_pushobject(v, self.inner.obj);
+			let s = if ffi::SQ_SUCCEEDED(ffi::sq_tostring(v, -1)) {
+				let mut p = ::std::ptr::null();
+				let mut s = None;
+				if ffi::SQ_SUCCEEDED(ffi::sq_getstring(v, -1, &mut p)) && !p.is_null() {
+					s = Some(::std::ffi::CStr::from_ptr(p).to_string_lossy().into_owned());
+				}
+				ffi::sq_poptop(v);
+				s
+			}
+			else {
+				None
+			};
+			ffi::sq_poptop(v);
+			s
+		}
+	}
+}
+
+impl Drop for HandleInner {
+	fn drop(&mut self) {
+		if self.alive.get() {
+			unsafe { ffi::sq_release(self.vm, &mut self.obj); }
+		}
+	}
+}
+
+impl PartialEq for Handle {
+	fn eq(&self, other: &Handle) -> bool {
+		self.inner.obj._type == other.inner.obj._type && self.inner.obj._unVal.raw == other.inner.obj._unVal.raw
+	}
+}
+
+impl Eq for Handle {}
+
+impl Hash for Handle {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		// Objects hash by their address, which is also all that is left once the virtual machine is closed
+		let hash = if self.is_alive() {
+			let v = self.inner.vm;
+			unsafe {
+				ffi::sq_pushobject(v, self.inner.obj);
+				let hash = ffi::sq_gethash(v, -1);
+				ffi::sq_poptop(v);
+				hash
+			}
+		}
+		else {
+			self.inner.obj._unVal.raw as ffi::SQHash
+		};
+		hash.hash(state);
+	}
+}
+
+impl PartialOrd for Handle {
+	/// Compares objects with `sq_cmp`, calling the `_cmp` metamethod of instances, tables and userdata.
+	///
+	/// Objects without `_cmp` are ordered by address, as are different objects that `_cmp` reports as equal,
+	/// since only a handle to the same object is equal. Objects of different types, and objects whose `_cmp`
+	/// metamethod throws or does not return an integer, are unordered.
+	fn partial_cmp(&self, other: &Handle) -> Option<Ordering> {
+		if self == other {
+			return Some(Ordering::Equal);
+		}
+		if self.object_type() != other.object_type() || !self.is_alive() || !other.is_alive() {
+			return None;
+		}
+		let v = self.inner.vm;
+		unsafe {
+			let top = ffi::sq_gettop(v);
+			// Keep any error waiting to be read, such as in an error handler, to restore it afterwards
+			ffi::sq_getlasterror(v);
+			ffi::sq_reseterror(v);
+			// sq_cmp compares the top of the stack with the object below it
+			ffi::sq_pushobject(v, other.inner.obj);
+			ffi::sq_pushobject(v, self.inner.obj);
+			let result = ffi::sq_cmp(v);
+			// sq_cmp has no way to report failure other than leaving the error set
+			ffi::sq_getlasterror(v);
+			let failed = ffi::sq_gettype(v, -1) != ffi::SQObjectType::OT_NULL;
+			// Throwing an object only sets it as the last error, which is null if there was none
+			ffi::sq_push(v, top + 1);
+			ffi::sq_throwobject(v);
+			ffi::sq_settop(v, top);
+			if failed {
+				None
+			}
+			else {
+				Some(result.cmp(&0).then(self.inner.obj._unVal.raw.cmp(&other.inner.obj._unVal.raw)))
+			}
+		}
+	}
+}
+
+impl fmt::Debug for Handle {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Handle({:?} @ {:#x})", self.inner.obj._type, self.inner.obj._unVal.raw)
+	}
+}
@@ -0,0 +1,647 @@
+//! Virtual filesystems for script file access.
+//!
+//! Once a `Vfs` is installed with `SquirrelVM::set_vfs`, the `file`, `loadfile`, `dofile`
+//! and `writeclosuretofile` functions of the `stdio` lib see the virtual tree instead of the real disk.
+//! Paths are always `/` separated and relative to the root of the filesystem.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use ffi;
+use {SquirrelVM, SquirrelError, Stream, RawReturn, ThrowError};
+#[cfg(target_os = "linux")]
+use stream;
+
+/// A filesystem that scripts can open files in.
+pub trait Vfs {
+	/// Opens the file at `path`.
+	fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn Stream>>;
+
+	/// Reads the whole file at `path`.
+	fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		let mut file = try!(self.open(path, OpenMode::parse("rb").unwrap()));
+		let mut data = Vec::new();
+		try!(file.read_to_end(&mut data));
+		Ok(data)
+	}
+
+	/// Replaces the contents of the file at `path`, creating it if needed.
+	fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+		let mut file = try!(self.open(path, OpenMode::parse("wb").unwrap()));
+		try!(file.write_all(data));
+		file.flush()
+	}
+
+	/// Lists the names of the entries in the directory at `dir`, in sorted order.
+	fn list(&self, dir: &str) -> io::Result<Vec<String>>;
+
+	/// Gets information about the file or directory at `path`.
+	fn stat(&self, path: &str) -> io::Result<Metadata>;
+}
+
+/// The way a file is opened, parsed from a C `fopen` mode string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenMode {
+	/// Whether the file can be read.
+	pub read: bool,
+	/// Whether the file can be written.
+	pub write: bool,
+	/// Whether every write goes to the end of the file.
+	pub append: bool,
+	/// Whether the file is created if it does not exist.
+	pub create: bool,
+	/// Whether the file is emptied when opened.
+	pub truncate: bool
+}
+
+impl OpenMode {
+	/// Parses a mode string such as `"r"`, `"wb"` or `"a+"`, as accepted by the `file` class.
+	///
+	/// The `b` flag is accepted and ignored, since files are always binary.
+	pub fn parse(mode: &str) -> Option<OpenMode> {
+		let mut chars = mode.chars().filter(|&c| c != 'b');
+		let base = match chars.next() {
+			Some('r') => OpenMode { read: true, write: false, append: false, create: false, truncate: false },
+			Some('w') => OpenMode { read: false, write: true, append: false, create: true, truncate: true },
+			Some('a') => OpenMode { read: false, write: true, append: true, create: true, truncate: false },
+			_ => return None
+		};
+		match chars.next() {
+			None => Some(base),
+			Some('+') if chars.next().is_none() => Some(OpenMode { read: true, write: true, .. base }),
+			_ => None
+		}
+	}
+}
+
+/// Information about an entry in a `Vfs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+	/// Whether the entry is a directory.
+	pub is_dir: bool,
+	/// The size of a file in bytes, or `0` for directories.
+	pub len: u64
+}
+
+/// Normalizes a virtual path, resolving `.` and `..` components.
+///
+/// Returns an error if the path leaves the root.
+pub fn normalize(path: &str) -> io::Result<String> {
+	let mut parts = Vec::new();
+	for part in path.split(['/', '\\']) {
+		match part {
+			"" | "." => {},
+			".." => if parts.pop().is_none() {
+				return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("'{}' is outside the filesystem", path)));
+			},
+			_ => parts.push(part)
+		}
+	}
+	Ok(parts.join("/"))
+}
+
+fn not_found(path: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::NotFound, format!("'{}' does not exist", path))
+}
+
+/// A filesystem held in memory.
+///
+/// Directories exist implicitly whenever they contain a file. Clones share the same files,
+/// so the host can keep a handle to read back what scripts have written.
+/// # Example
+/// ```
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// let vfs = MemoryVfs::new();
+/// vfs.insert("mods/hello.nut", b"print(\"hello\")".to_vec()).unwrap();
+/// vm.set_vfs(vfs.clone()).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemoryVfs {
+	files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>
+}
+
+impl MemoryVfs {
+	/// Creates an empty filesystem.
+	pub fn new() -> MemoryVfs {
+		MemoryVfs::default()
+	}
+
+	/// Adds or replaces the file at `path`.
+	pub fn insert(&self, path: &str, data: Vec<u8>) -> io::Result<()> {
+		let path = try!(self.file_path(path));
+		self.files.borrow_mut().insert(path, data);
+		Ok(())
+	}
+
+	/// Gets a copy of the contents of the file at `path`.
+	pub fn get(&self, path: &str) -> Option<Vec<u8>> {
+		let path = match normalize(path) {
+			Ok(path) => path,
+			Err(_) => return None
+		};
+		self.files.borrow().get(&path).cloned()
+	}
+
+	/// Removes the file at `path`, returning its contents.
+	pub fn remove(&self, path: &str) -> Option<Vec<u8>> {
+		let path = match normalize(path) {
+			Ok(path) => path,
+			Err(_) => return None
+		};
+		self.files.borrow_mut().remove(&path)
+	}
+
+	/// Normalizes a path that must name a file rather than a directory.
+	fn file_path(&self, path: &str) -> io::Result<String> {
+		let normalized = try!(normalize(path));
+		if normalized.is_empty() || self.is_dir(&normalized) {
+			return Err(io::Error::other(format!("'{}' is a directory", path)));
+		}
+		Ok(normalized)
+	}
+
+	fn is_dir(&self, path: &str) -> bool {
+		if path.is_empty() {
+			return true;
+		}
+		let prefix = format!("{}/", path);
+		let files = self.files.borrow();
+		files.range(prefix.clone()..).next().is_some_and(|(k, _)| k.starts_with(&prefix))
+	}
+}
+
+impl Vfs for MemoryVfs {
+	fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn Stream>> {
+		let normalized = try!(self.file_path(path));
+		{
+			let mut files = self.files.borrow_mut();
+			if !files.contains_key(&normalized) {
+				if !mode.create {
+					return Err(not_found(path));
+				}
+				files.insert(normalized.clone(), Vec::new());
+			}
+			else if mode.truncate {
+				files.insert(normalized.clone(), Vec::new());
+			}
+		}
+		Ok(Box::new(MemoryFile {
+			files: self.files.clone(),
+			path: normalized,
+			pos: 0,
+			mode: mode
+		}))
+	}
+
+	fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+		self.get(path).ok_or_else(|| not_found(path))
+	}
+
+	fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+		self.insert(path, data.to_vec())
+	}
+
+	fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+		let dir = try!(normalize(dir));
+		if !self.is_dir(&dir) {
+			return Err(not_found(&dir));
+		}
+		let prefix = if dir.is_empty() { dir } else { format!("{}/", dir) };
+
+		let files = self.files.borrow();
+		let mut names: Vec<String> = files.range(prefix.clone()..)
+			.take_while(|&(k, _)| k.starts_with(&prefix))
+			.map(|(k, _)| k[prefix.len()..].split('/').next().unwrap().to_string())
+			.collect();
+		// Keys are ordered by their full path, so "a.txt" comes before the directory "a" of "a/b"
+		names.sort();
+		names.dedup();
+		Ok(names)
+	}
+
+	fn stat(&self, path: &str) -> io::Result<Metadata> {
+		let normalized = try!(normalize(path));
+		if let Some(data) = self.files.borrow().get(&normalized) {
+			return Ok(Metadata { is_dir: false, len: data.len() as u64 });
+		}
+		if self.is_dir(&normalized) {
+			Ok(Metadata { is_dir: true, len: 0 })
+		}
+		else {
+			Err(not_found(path))
+		}
+	}
+}
+
+/// An open file in a `MemoryVfs`.
+///
+/// Reads and writes go straight to the shared file, so they are visible to other handles immediately.
+struct MemoryFile {
+	files: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+	path: String,
+	pos: u64,
+	mode: OpenMode
+}
+
+impl MemoryFile {
+	fn with_data<T, F: FnOnce(&mut Vec<u8>) -> T>(&self, f: F) -> io::Result<T> {
+		match self.files.borrow_mut().get_mut(&self.path) {
+			Some(data) => Ok(f(data)),
+			None => Err(not_found(&self.path))
+		}
+	}
+}
+
+impl Read for MemoryFile {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		if !self.mode.read {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the file is not open for reading"));
+		}
+		let pos = self.pos as usize;
+		let n = try!(self.with_data(|data| {
+			let available = data.get(pos..).unwrap_or(&[]);
+			let n = available.len().min(buf.len());
+			buf[..n].copy_from_slice(&available[..n]);
+			n
+		}));
+		self.pos += n as u64;
+		Ok(n)
+	}
+}
+
+impl Write for MemoryFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if !self.mode.write {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the file is not open for writing"));
+		}
+		let (append, pos) = (self.mode.append, self.pos as usize);
+		self.pos = try!(self.with_data(|data| {
+			let start = if append { data.len() } else { pos };
+			let end = start + buf.len();
+			if data.len() < end {
+				data.resize(end, 0);
+			}
+			data[start..end].copy_from_slice(buf);
+			end as u64
+		}));
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+impl Seek for MemoryFile {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let len = try!(self.with_data(|data| data.len() as i64));
+		let pos = match pos {
+			SeekFrom::Start(n) => n as i64,
+			SeekFrom::Current(n) => self.pos as i64 + n,
+			SeekFrom::End(n) => len + n
+		};
+		if pos < 0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of the file"));
+		}
+		self.pos = pos as u64;
+		Ok(self.pos)
+	}
+}
+
+/// A filesystem that exposes a single directory of the real disk.
+///
+/// Paths cannot leave the directory, either with `..` or by following symbolic links.
+/// # Example
+/// ```no_run
+/// # use squirrel::*;
+/// # use std::io::{stdout, stderr};
+/// # let mut vm = SquirrelVM::new(1024, stdout(), stderr());
+/// vm.set_vfs(JailVfs::new("saves").unwrap()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct JailVfs {
+	root: PathBuf
+}
+
+impl JailVfs {
+	/// Creates a filesystem rooted at the existing directory `root`.
+	pub fn new<Q: AsRef<Path>>(root: Q) -> io::Result<JailVfs> {
+		let root = try!(root.as_ref().canonicalize());
+		if !root.is_dir() {
+			return Err(io::Error::other(format!("'{}' is not a directory", root.display())));
+		}
+		Ok(JailVfs {
+			root: root
+		})
+	}
+
+	/// Gets the directory the filesystem is rooted at.
+	pub fn root(&self) -> &Path {
+		&self.root
+	}
+
+	/// Resolves a virtual path to a location on disk, checking that it stays inside the root.
+	fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+		let full = self.root.join(try!(normalize(path)));
+		// Files that are about to be created do not exist yet, so resolve their parent instead
+		let resolved = match full.canonicalize() {
+			Ok(p) => p,
+			Err(_) => match (full.parent().and_then(|p| p.canonicalize().ok()), full.file_name()) {
+				(Some(parent), Some(name)) => parent.join(name),
+				_ => return Err(not_found(path))
+			}
+		};
+		if resolved.starts_with(&self.root) {
+			Ok(resolved)
+		}
+		else {
+			Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("'{}' is outside the filesystem", path)))
+		}
+	}
+}
+
+impl Vfs for JailVfs {
+	fn open(&self, path: &str, mode: OpenMode) -> io::Result<Box<dyn Stream>> {
+		let file = try!(OpenOptions::new()
+			.read(mode.read)
+			.write(mode.write && !mode.append)
+			.append(mode.append)
+			.create(mode.create)
+			.truncate(mode.truncate)
+			.open(try!(self.resolve(path))));
+		Ok(Box::new(file))
+	}
+
+	fn list(&self, dir: &str) -> io::Result<Vec<String>> {
+		let mut names = Vec::new();
+		for entry in try!(fs::read_dir(try!(self.resolve(dir)))) {
+			names.push(try!(entry).file_name().to_string_lossy().into_owned());
+		}
+		names.sort();
+		Ok(names)
+	}
+
+	fn stat(&self, path: &str) -> io::Result<Metadata> {
+		let meta = try!(fs::metadata(try!(self.resolve(path))));
+		Ok(Metadata {
+			is_dir: meta.is_dir(),
+			len: if meta.is_dir() { 0 } else { meta.len() }
+		})
+	}
+}
+
+/// Replaces the file functions of the `stdio` lib in the root table with ones that use `vfs`.
+pub fn install<P, E>(vm: &mut SquirrelVM<P, E>, vfs: Rc<dyn Vfs>) -> Result<(), SquirrelError>
+	where P: Write + Sync, E: Write + Sync
+{
+	let mut vm = vm.guard();
+
+	// Scripts can reach the class through `stdout.getclass()` or any open file, so its constructor must use the vfs
+	vm.push_registry_table();
+	vm.push_value(FILE_CLASS);
+	if vm.raw_get(-2).is_ok() {
+		vm.push_value("constructor");
+		vm.push_value("constructor");
+		if vm.get(-3).is_ok() {
+			let vfs = vfs.clone();
+			vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+				// Handles to open streams are passed by the host, such as by `sqstd_createfile` or `push_stream`
+				if vm.get_top() > 2 && unsafe { ffi::sq_gettype(vm.0, 2) } == ffi::SQObjectType::OT_USERPOINTER {
+					return call_original(vm);
+				}
+				construct_file(vm, &*vfs)
+			}, 1);
+			try!(new_slot(&mut vm, "constructor"));
+		}
+		else {
+			vm.reset_error();
+			vm.pop(1);
+		}
+		vm.pop(1);
+	}
+	else {
+		vm.reset_error();
+	}
+	vm.pop(1);
+
+	vm.push_root_table();
+	#[cfg(not(target_os = "linux"))]
+	{
+		// Streams cannot be exposed as files without fopencookie
+		vm.push_value("file");
+		if vm.delete_slot(-2, false).is_err() {
+			vm.reset_error();
+		}
+	}
+
+	vm.push_value("loadfile");
+	let loader = vfs.clone();
+	vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+		match load(vm, &*loader) {
+			Ok(()) => RawReturn(1),
+			Err(e) => RawReturn(e.throw(vm))
+		}
+	}, 0);
+	try!(new_slot(&mut vm, "loadfile"));
+
+	vm.push_value("dofile");
+	let loader = vfs.clone();
+	vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| {
+		if let Err(e) = load(vm, &*loader) {
+			return RawReturn(e.throw(vm));
+		}
+		// Run the script with the same `this` as dofile itself
+		vm.push(1);
+		match vm.call(1, true, false) {
+			Ok(()) => RawReturn(1),
+			Err(()) => RawReturn(ffi::SQ_ERROR)
+		}
+	}, 0);
+	try!(new_slot(&mut vm, "dofile"));
+
+	vm.push_value("writeclosuretofile");
+	vm.new_internal_closure(move |vm: &mut SquirrelVM<P, E>| -> Result<(), SquirrelError> {
+		let path: String = try!(vm.read(2));
+		let mut data = Vec::new();
+		vm.push(3);
+		try!(vm.write_closure(&mut data));
+		vm.pop(1);
+		vfs.write(&path, &data).map_err(|e| io_error(&path, e))
+	}, 0);
+	new_slot(&mut vm, "writeclosuretofile")
+}
+
+/// The registry slot the `stdio` lib keeps the `file` class in.
+const FILE_CLASS: &'static str = "std_file";
+
+fn new_slot<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, name: &str) -> Result<(), SquirrelError> {
+	vm.new_slot(-3, false).map_err(|_| SquirrelError::Runtime(format!("could not replace '{}'", name)))
+}
+
+fn io_error(path: &str, e: io::Error) -> SquirrelError {
+	SquirrelError::Io {
+		path: PathBuf::from(path),
+		desc: e.to_string()
+	}
+}
+
+/// Calls the original constructor of the `file` class, the free variable at the top of the stack, with the same arguments.
+fn call_original<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>) -> RawReturn {
+	let top = vm.get_top();
+	vm.push(top);
+	for i in 1..top {
+		vm.push(i);
+	}
+	match vm.call(top - 1, false, false) {
+		Ok(()) => RawReturn(0),
+		Err(()) => RawReturn(ffi::SQ_ERROR)
+	}
+}
+
+/// Implements the constructor of the `file` class, `file(path, mode)`, opening the stream in `vfs`.
+///
+/// The original constructor is the free variable, at the top of the stack. It is called with a handle to the
+/// opened stream, which it takes ownership of.
+#[cfg(target_os = "linux")]
+fn construct_file<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, vfs: &dyn Vfs) -> RawReturn {
+	let top = vm.get_top();
+	let result = vm.read::<String>(2).and_then(|path| {
+		let mode: String = try!(vm.read(3));
+		let parsed = try!(OpenMode::parse(&mode).ok_or_else(|| SquirrelError::Runtime(format!("invalid file mode '{}'", mode))));
+		let stream = try!(vfs.open(&path, parsed).map_err(|e| io_error(&path, e)));
+		let file = stream::open(stream);
+		if file.is_null() {
+			return Err(SquirrelError::Runtime("could not open a handle for the stream".to_string()));
+		}
+		Ok(file)
+	});
+	let file = match result {
+		Ok(file) => file,
+		Err(e) => return RawReturn(e.throw(vm))
+	};
+
+	vm.push(top);
+	vm.push(1);
+	unsafe { ffi::sq_pushuserpointer(vm.0, file as ffi::SQUserPointer); }
+	// Any value but null tells the constructor to close the handle when the object is released
+	vm.push_integer(1);
+	match vm.call(3, false, false) {
+		Ok(()) => RawReturn(0),
+		Err(()) => RawReturn(ffi::SQ_ERROR)
+	}
+}
+
+/// Stands in for the constructor of the `file` class where streams cannot be exposed as files.
+#[cfg(not(target_os = "linux"))]
+fn construct_file<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, _: &dyn Vfs) -> RawReturn {
+	RawReturn("files cannot be opened on this platform".throw(vm))
+}
+
+/// Loads the script named by the first argument, pushing it as a closure.
+fn load<P: Write + Sync, E: Write + Sync>(vm: &mut SquirrelVM<P, E>, vfs: &dyn Vfs) -> Result<(), SquirrelError> {
+	let path: String = try!(vm.read(2));
+	let data = try!(vfs.read(&path).map_err(|e| io_error(&path, e)));
+	vm.load_bytes(&data, &path)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Vfs, MemoryVfs, JailVfs, OpenMode, Metadata, normalize};
+	use std::env;
+	use std::fs;
+	use std::io::{Read, Write, Seek, SeekFrom};
+
+	#[test]
+	fn parse_modes() {
+		let r = OpenMode::parse("rb").unwrap();
+		assert!(r.read && !r.write && !r.create);
+		let w = OpenMode::parse("w").unwrap();
+		assert!(!w.read && w.write && w.create && w.truncate);
+		let a = OpenMode::parse("a+").unwrap();
+		assert!(a.read && a.write && a.append && !a.truncate);
+		assert_eq!(OpenMode::parse("r+b"), OpenMode::parse("rb+"));
+		assert_eq!(OpenMode::parse(""), None);
+		assert_eq!(OpenMode::parse("x"), None);
+		assert_eq!(OpenMode::parse("r++"), None);
+	}
+
+	#[test]
+	fn normalize_paths() {
+		assert_eq!(normalize("./a//b/../c.nut").unwrap(), "a/c.nut");
+		assert_eq!(normalize("a\\b").unwrap(), "a/b");
+		assert_eq!(normalize("/").unwrap(), "");
+		assert!(normalize("a/../../b").is_err());
+	}
+
+	#[test]
+	fn memory_files() {
+		let vfs = MemoryVfs::new();
+		vfs.insert("a/b.nut", b"one".to_vec()).unwrap();
+		vfs.insert("a.txt", b"two".to_vec()).unwrap();
+		vfs.insert("./a/c/d.nut", Vec::new()).unwrap();
+
+		assert_eq!(vfs.get("a/./b.nut"), Some(b"one".to_vec()));
+		assert_eq!(vfs.list("").unwrap(), ["a", "a.txt"]);
+		assert_eq!(vfs.list("a").unwrap(), ["b.nut", "c"]);
+		assert!(vfs.list("a.txt").is_err());
+		assert_eq!(vfs.stat("a").unwrap(), Metadata { is_dir: true, len: 0 });
+		assert_eq!(vfs.stat("a/b.nut").unwrap(), Metadata { is_dir: false, len: 3 });
+		assert!(vfs.stat("missing").is_err());
+		// A directory cannot be replaced by a file
+		assert!(vfs.insert("a", Vec::new()).is_err());
+		assert_eq!(vfs.remove("a.txt"), Some(b"two".to_vec()));
+		assert_eq!(vfs.get("a.txt"), None);
+	}
+
+	#[test]
+	fn memory_streams() {
+		let vfs = MemoryVfs::new();
+		assert!(vfs.open("new.txt", OpenMode::parse("r").unwrap()).is_err());
+
+		let mut file = vfs.open("new.txt", OpenMode::parse("w+").unwrap()).unwrap();
+		file.write_all(b"hello").unwrap();
+		file.seek(SeekFrom::Start(1)).unwrap();
+		let mut text = String::new();
+		file.read_to_string(&mut text).unwrap();
+		assert_eq!(text, "ello");
+		assert!(file.seek(SeekFrom::Current(-10)).is_err());
+
+		let mut append = vfs.open("new.txt", OpenMode::parse("a").unwrap()).unwrap();
+		append.write_all(b" world").unwrap();
+		assert!(append.read(&mut [0; 4]).is_err());
+		assert_eq!(vfs.read("new.txt").unwrap(), b"hello world");
+
+		vfs.open("new.txt", OpenMode::parse("w").unwrap()).unwrap();
+		assert_eq!(vfs.read("new.txt").unwrap(), b"");
+	}
+
+	#[test]
+	fn clones_share_files() {
+		let vfs = MemoryVfs::new();
+		vfs.clone().write("a.nut", b"1").unwrap();
+		assert_eq!(vfs.read("a.nut").unwrap(), b"1");
+	}
+
+	#[test]
+	fn jail_stays_inside() {
+		let root = env::temp_dir().join(format!("squirrel-vfs-{}", ::std::process::id()));
+		fs::create_dir_all(root.join("jail/sub")).unwrap();
+		fs::write(root.join("outside.txt"), "").unwrap();
+		let vfs = JailVfs::new(root.join("jail")).unwrap();
+
+		vfs.write("sub/a.txt", b"data").unwrap();
+		assert_eq!(vfs.read("sub/a.txt").unwrap(), b"data");
+		assert_eq!(vfs.list("").unwrap(), ["sub"]);
+		assert_eq!(vfs.stat("sub/a.txt").unwrap(), Metadata { is_dir: false, len: 4 });
+		assert!(vfs.read("../outside.txt").is_err());
+		assert!(JailVfs::new(root.join("outside.txt")).is_err());
+
+		fs::remove_dir_all(&root).unwrap();
+	}
+}